@@ -6,7 +6,7 @@
 //
 //! cRUST is a vst software synthesizer plugin written in Rust using the vst crate.
 //! It has 2 oscillators, each of which are switchable between sine, saw, square,
-//! and triangle waveforms. cRUST also has a noise generator as well as an ADSR
+//! triangle, and wavetable waveforms. cRUST also has a noise generator as well as an ADSR
 //! envelope filter. cRUST is a work in progress and has only been fully
 //! tested on macOS High Sierra using Cubase and Ableton DAWs.
 
@@ -15,54 +15,287 @@ extern crate vst;
 extern crate rand;
 
 use vst::buffer::AudioBuffer;
-use vst::plugin::{Category, Plugin, Info};
+use vst::plugin::{Category, Plugin, Info, HostCallback};
+use vst::editor::Editor;
+use vst::host::Host;
 use vst::event::Event;
 use vst::api::Events;
 use std::f64::consts::PI;
-use rand::random;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use rand::{random, Rng, SeedableRng};
+use rand::rngs::SmallRng;
+
+/// A parameter that glides towards the value set by the host instead of jumping
+/// straight to it, which avoids zipper noise when a knob is turned.
+struct SmoothedParam {
+    current: f32,
+    target: f32,
+}
+
+impl SmoothedParam {
+    fn new(value: f32) -> SmoothedParam {
+        SmoothedParam {
+            current: value,
+            target: value,
+        }
+    }
+
+    /// Moves the current value one sample closer to the target and returns it.
+    /// Once it is close enough it lands on the target instead of creeping towards it forever.
+    fn next(&mut self, coefficient: f32) -> f32 {
+        let step = flush_denormal((self.target - self.current) * coefficient);
+        if step == 0.0 {
+            self.current = self.target;
+        } else {
+            self.current += step;
+        }
+        self.current
+    }
+}
+
+/// Widens or narrows a stereo frame with mid/side processing. A width of 1
+/// leaves it as it is, 0 folds it to mono, and above 1 pushes the sides out.
+fn stereo_width(frame: [f32; 2], width: f32) -> [f32; 2] {
+    if width == 1.0 {
+        return frame;
+    }
+
+    let mid = (frame[0] + frame[1]) / 2.0;
+    let side = (frame[0] - frame[1]) / 2.0 * width;
+
+    [mid + side, mid - side]
+}
+
+/// Anything quieter than this in a feedback path is treated as silence.
+const DENORMAL_THRESHOLD: f32 = 1e-20;
+
+/// Flushes values that have decayed below DENORMAL_THRESHOLD to exactly zero.
+/// Feedback loops that fade out otherwise end up on denormal numbers, which
+/// are very slow on some CPUs.
+fn flush_denormal(value: f32) -> f32 {
+    if value.abs() < DENORMAL_THRESHOLD {
+        0.0
+    } else {
+        value
+    }
+}
+
+/// Time constant in seconds used for parameter smoothing.
+const SMOOTHING_TIME: f64 = 0.005;
+
+/// Per sample coefficient of a one pole smoother, so that a smoothed parameter
+/// covers about 63% of the distance to its target every SMOOTHING_TIME seconds.
+fn smoothing_coefficient(sample_rate: f64) -> f32 {
+    (1.0 - (-1.0 / (SMOOTHING_TIME * sample_rate)).exp()) as f32
+}
+
+/// Names of the oscillator waveforms in the order they appear on the slider.
+const WAVEFORM_NAMES: [&str; 5] = ["Sine", "Saw", "Square", "Triangle", "Wavetable"];
+
+/// Snaps a waveform slider value onto one of the waveforms, each of
+/// which gets an equal share of the slider.
+fn waveform_step(val: f32) -> usize {
+    ((val * WAVEFORM_NAMES.len() as f32) as usize).min(WAVEFORM_NAMES.len() - 1)
+}
+
+/// Picks the waveform for a slider value saved by a version 1 preset, when the
+/// slider only had sine, saw, square, and triangle.
+fn legacy_waveform_step(val: f32) -> usize {
+    if val < 0.33 {
+        0
+    } else if val < 0.66 {
+        1
+    } else if val < 1.0 {
+        2
+    } else {
+        3
+    }
+}
+
+/// Slider value that selects a waveform, the inverse of waveform_step.
+fn waveform_value(wave_index: usize) -> f32 {
+    wave_index as f32 / (WAVEFORM_NAMES.len() - 1) as f32
+}
+
+/// Number of waveforms the morph sweeps through: sine, saw, square, and triangle.
+const MORPH_WAVEFORMS: usize = 4;
+
+/// Splits a morph position into the waveform it fades from and how far it
+/// has faded into the next one. Each neighbouring pair gets an equal share of the slider.
+fn morph_blend(position: f32) -> (usize, f32) {
+    let scaled = position.clamp(0.0, 1.0) * (MORPH_WAVEFORMS - 1) as f32;
+    let index = (scaled as usize).min(MORPH_WAVEFORMS - 2);
+
+    (index, scaled - index as f32)
+}
+
+/// Shows a morph position as the two waveforms being blended.
+fn morph_text(position: f32) -> String {
+    let (index, amount) = morph_blend(position);
+    format!("{} to {}, {}%", WAVEFORM_NAMES[index], WAVEFORM_NAMES[index + 1], (amount * 100.0).round())
+}
+
+/// Peak level of the square wave in balanced mode. A full scale square is much
+/// louder than the other waveforms, so it is turned down to sit alongside them.
+const SQUARE_BALANCE: f32 = 0.4;
+
+/// Number of samples in one cycle of a wavetable.
+const WAVETABLE_SIZE: usize = 256;
 
 /// Stores data that is unique to each Oscillator.
 struct Oscillator {
-    volume: f32,
-    wave_index: f32,
-    detune: f32,
+    volume: SmoothedParam,
+    wave_index: usize,
+    detune: SmoothedParam,
+    pulse_width: f32,
+    pan: f32,
+    table_position: f32,
+    transpose: i32,
+    phase_offset: f32,
+    full_scale_square: f32,
+    morph: f32,
+    morph_position: f32,
+    enabled: f32,
+    fixed: f32,
+    fixed_freq: f32,
 }
 
 /// Default Oscillator values.
 impl Default for Oscillator {
     fn default() -> Oscillator {
         Oscillator {
-            volume: 0.5,
-            wave_index: 0.0,
-            detune: 0.0,
+            volume: SmoothedParam::new(0.5),
+            wave_index: 0,
+            detune: SmoothedParam::new(0.0),
+            pulse_width: 0.5,
+            pan: 0.5,
+            table_position: 0.0,
+            transpose: 0,
+            phase_offset: 0.0,
+            full_scale_square: 0.0,
+            morph: 0.0,
+            morph_position: 0.0,
+            enabled: 1.0,
+            fixed: 0.0,
+            fixed_freq: 0.5,
         }
     }
 }
 
-// #[derive(PartialEq)]
-// struct Note {
-//     midi_note: u8,
-//     is_active: bool,
-// }
-//
-// impl Default for Note {
-//     fn default() -> Note {
-//         Note {
-//             midi_note: 0,
-//             is_active: false,
-//         }
-//     }
-// }
-
-/// Stores data that is relevent to the ADSR Envelope filter.
+impl Oscillator {
+    /// The note this oscillator plays for a key, shifted by its transpose
+    /// in semitones and kept inside the MIDI note range.
+    fn transposed(&self, note: u8) -> u8 {
+        (note as i32 + self.transpose).clamp(0, 127) as u8
+    }
+
+    /// Frequency of the fixed frequency mode, mapped logarithmically
+    /// from 20 Hz to 20 kHz across the slider.
+    fn fixed_hz(&self) -> f64 {
+        20.0 * 1000.0_f64.powf(self.fixed_freq as f64)
+    }
+
+    /// Frequency this oscillator plays for a key, detuned by the given cents and
+    /// bent by the given semitones. In fixed frequency mode the key, transpose,
+    /// and pitch bend are ignored and only the detune applies.
+    fn frequency(&self, note: u8, cents: f32, tuning: &Tuning, pitch: f32) -> f64 {
+        if self.fixed >= 0.5 {
+            self.fixed_hz() * (cents as f64 / 1200.0).exp2()
+        } else {
+            bend_freq(midi_note_num_to_freq(self.transposed(note), cents, tuning), pitch)
+        }
+    }
+
+    /// The upper half of the enabled slider switches the oscillator on.
+    fn is_enabled(&self) -> bool {
+        self.enabled >= 0.5
+    }
+
+    /// Peak level of this oscillator's square wave. The upper half of the
+    /// square level slider gives a full scale square.
+    fn square_level(&self) -> f32 {
+        if self.full_scale_square >= 0.5 {
+            1.0
+        } else {
+            SQUARE_BALANCE
+        }
+    }
+}
+
+/// Single cycle waveforms that the wavetable oscillator morphs between.
+/// The table position sweeps through them in order.
+struct Wavetable {
+    tables: Vec<Vec<f32>>,
+}
+
+/// The built in tables go from a sine through an organ tone and a saw to a square.
+impl Default for Wavetable {
+    fn default() -> Wavetable {
+        let saw: Vec<f32> = (1..=16).map(|harmonic| 1.0 / harmonic as f32).collect();
+        let square: Vec<f32> = (1..=16).map(|harmonic| if harmonic % 2 == 1 { 1.0 / harmonic as f32 } else { 0.0 }).collect();
+
+        Wavetable {
+            tables: vec![
+                Wavetable::additive(&[1.0]),
+                Wavetable::additive(&[1.0, 0.5, 0.0, 0.3, 0.0, 0.0, 0.0, 0.2]),
+                Wavetable::additive(&saw),
+                Wavetable::additive(&square),
+            ],
+        }
+    }
+}
+
+impl Wavetable {
+    /// Builds a table by adding up sine harmonics, then scales it to peak at 1.
+    /// Tables are kept to a few harmonics so they don't alias too much on high notes.
+    fn additive(harmonics: &[f32]) -> Vec<f32> {
+        let table: Vec<f32> = (0..WAVETABLE_SIZE).map(|index| {
+            let phase = index as f64 / WAVETABLE_SIZE as f64;
+            harmonics.iter().enumerate().map(|(harmonic, &amplitude)| {
+                amplitude * (phase * 2.0 * PI * (harmonic + 1) as f64).sin() as f32
+            }).sum()
+        }).collect();
+        let peak = table.iter().fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+
+        table.iter().map(|sample| sample / peak).collect()
+    }
+
+    /// Reads a table at a phase between 0 and 1, interpolating between samples.
+    fn read(table: &[f32], phase: f64) -> f32 {
+        let position = phase.rem_euclid(1.0) * table.len() as f64;
+        let index = position as usize % table.len();
+        let fraction = (position - position.floor()) as f32;
+        let next = table[(index + 1) % table.len()];
+
+        table[index] + (next - table[index]) * fraction
+    }
+
+    /// Reads the tables at a position between 0 (first table) and 1 (last table),
+    /// crossfading between the two nearest tables.
+    fn sample(&self, position: f32, phase: f64) -> f32 {
+        let scaled = position.clamp(0.0, 1.0) * (self.tables.len() - 1) as f32;
+        let index = (scaled as usize).min(self.tables.len() - 1);
+        let fraction = scaled - index as f32;
+        let current = Wavetable::read(&self.tables[index], phase);
+        if fraction == 0.0 {
+            return current;
+        }
+
+        current + (Wavetable::read(&self.tables[index + 1], phase) - current) * fraction
+    }
+}
+
+/// Stores the ADSR settings of the Envelope filter, which are shared by every voice.
 struct Envelope {
     attack: f32,
     decay: f32,
     sustain: f32,
     release: f32,
-    duration: f64,
-    end_time: f64,
-    note_on: bool,
+    attack_curve: f32,
+    decay_curve: f32,
+    release_curve: f32,
 }
 
 /// Default Envelope filter values.
@@ -73,410 +306,6423 @@ impl Default for Envelope {
             decay: 0.05,
             sustain: 0.16,
             release: 0.14,
-            duration: 0.0,
-            end_time: 0.0,
-            note_on: false,
+            attack_curve: 0.0,
+            decay_curve: 0.0,
+            release_curve: 0.0,
         }
     }
 }
 
-/// Stores values for the synth as a whole.
-struct Crust {
-    time: f64,
-    sample_rate: f64,
-    oscillators: Vec<Oscillator>,
-    notes: Vec<u8>,
-    noise: f32,
-    envelope: Envelope,
-    master_vol: f32,
+/// Stores the pitch of A4 in Hz and a cents offset for each of the 12 pitch
+/// classes, starting from C, which lets the synth play in other temperaments.
+struct Tuning {
+    reference: f32,
+    cents: [f32; 12],
 }
 
-/// Default synth values.
-impl Default for Crust {
-    fn default() -> Crust {
-        Crust {
-            time: 0.0,
-            sample_rate: 44100.0,
-            oscillators: vec![Default::default(), Default::default()],
-            notes: Vec::new(),
-            noise: 0.0,
-            envelope: Envelope::default(),
-            master_vol: 1.0,
+/// Default Tuning values, equal temperament at A4 = 440 Hz.
+impl Default for Tuning {
+    fn default() -> Tuning {
+        Tuning {
+            reference: 440.0,
+            cents: [0.0; 12],
         }
     }
 }
 
-/// Creates a sine wave based on midi note, oscillator volume, time, and detune value.
-fn create_sine_wave(midi_note: u8, volume: f32, time: f64, detune: f32) -> f32 {
-    volume * (time as f32 * midi_note_num_to_freq(midi_note, detune) as f32 * 2.0 * PI as f32).sin()
+/// Release time in seconds of a voice that was stolen to make room for a new note.
+const STOLEN_VOICE_RELEASE: f32 = 0.005;
+
+/// Detune in cents of the outermost unison copies at full spread.
+const UNISON_SPREAD_CENTS: f32 = 50.0;
+
+/// Note where filter key tracking leaves the cutoff where the slider puts it.
+const KEY_TRACK_CENTER: u8 = 60;
+
+/// Most detuned copies of each oscillator that unison mode can stack up.
+const MAX_UNISON: usize = 7;
+
+/// Frames the render buffer holds before the host says how big its blocks are.
+const DEFAULT_BLOCK_SIZE: usize = 1024;
+
+/// Version byte written at the start of saved preset data. Version 2 added the
+/// wavetable as a fifth position on the waveform sliders.
+const PRESET_VERSION: u8 = 2;
+
+/// Plugin ID hosts use to find cRUST in saved projects, spelled out as "cRST".
+const UNIQUE_ID: i32 = i32::from_be_bytes(*b"cRST");
+
+/// Plugin version reported to the host, 0.1.0 to match Cargo.toml.
+const VERSION: i32 = 10;
+
+/// A named sound that ships with the plugin. Parameters that aren't listed
+/// keep their default values.
+struct FactoryPreset {
+    name: &'static str,
+    parameters: &'static [(i32, f32)],
 }
 
-/// Creates a sawtooth wave based on midi note, oscillator volume, time, and detune value.
-fn create_sawtooth_wave(midi_note: u8, volume: f32, time: f64, detune: f32) -> f32 {
-    volume * (time *  midi_note_num_to_freq(midi_note, detune) - ((time *  midi_note_num_to_freq(midi_note, detune)).floor()) - 0.5) as f32
+/// The built in sounds, listed by the host as the plugin's programs.
+const FACTORY_PRESETS: [FactoryPreset; 6] = [
+    FactoryPreset {
+        name: "Init",
+        parameters: &[],
+    },
+    FactoryPreset {
+        name: "Warm Pad",
+        parameters: &[(0, 0.25), (3, 0.25), (5, 0.55), (7, 0.2), (8, 0.2), (9, 0.8), (10, 0.3),
+                      (14, 0.6), (15, 0.2), (23, 0.3), (24, 0.7), (30, 0.34), (31, 0.3)],
+    },
+    FactoryPreset {
+        name: "Acid Bass",
+        parameters: &[(0, 0.25), (4, 0.0), (7, 0.0), (8, 0.05), (9, 0.2), (10, 0.02), (14, 0.35),
+                      (15, 0.8), (21, 0.0), (22, 0.05), (25, 0.5), (34, 1.0), (35, 0.3)],
+    },
+    FactoryPreset {
+        name: "Bell",
+        parameters: &[(0, 0.0), (3, 0.0), (5, 0.9), (7, 0.0), (8, 0.4), (9, 0.0), (10, 0.4),
+                      (16, 0.7), (27, 0.6), (28, 1.0), (108, 0.7), (109, 0.7)],
+    },
+    FactoryPreset {
+        name: "Square Lead",
+        parameters: &[(0, 0.5), (3, 0.5), (5, 0.52), (9, 0.7), (12, 0.3), (17, 0.45), (18, 0.15),
+                      (21, 0.0), (22, 0.03)],
+    },
+    FactoryPreset {
+        name: "Tremolo Organ",
+        parameters: &[(0, 0.0), (3, 0.75), (7, 0.0), (9, 1.0), (10, 0.02), (17, 0.5), (18, 0.4),
+                      (20, 1.0), (25, 0.4)],
+    },
+];
+
+/// Stores a single sounding note along with its own envelope state, so that
+/// each note of a chord can be started and released independently.
+/// Each unison copy keeps its own phase for oscillator 1 and oscillator 2.
+struct Voice {
+    midi_note: u8,
+    velocity: u8,
+    phases: [[f64; 2]; MAX_UNISON],
+    sub_phase: f64,
+    duration: f64,
+    end_time: f64,
+    note_on: bool,
+    stolen: bool,
+    release_level: f32,
+    filter_release_level: f32,
+    drift: Drift,
 }
 
-/// Creates a square wave based on midi note, oscillator volume, time, and detune value.
-fn create_square_wave(midi_note: u8, volume: f32, time: f64, detune: f32) -> f32 {
-    if (time * midi_note_num_to_freq(midi_note, detune) * 2.0 * PI).sin() as f32 >= 0.0 {
-        volume * 0.4 // not using 1.0 in order to balance with other waveforms
-    } else {
-        volume * -0.4
+impl Voice {
+    /// Every unison copy starts at phase 0. start_note spreads the copies out
+    /// with the synth's random generator.
+    fn new(midi_note: u8, velocity: u8) -> Voice {
+        Voice {
+            midi_note: midi_note,
+            velocity: velocity,
+            phases: [[0.0; 2]; MAX_UNISON],
+            sub_phase: 0.0,
+            duration: 0.0,
+            end_time: 0.0,
+            note_on: true,
+            stolen: false,
+            release_level: 0.0,
+            filter_release_level: 0.0,
+            drift: Drift::new(0),
+        }
     }
-}
 
-/// Creates a triangle wave based on midi note, oscillator volume, time, and detune value.
-fn create_triangle_wave(midi_note: u8, volume: f32, time: f64, detune: f32) -> f32 {
-    volume * ((((time *  midi_note_num_to_freq(midi_note, detune)) - ((time *  midi_note_num_to_freq(midi_note, detune)).floor()) - 0.5).abs() - 0.25) * 4.0) as f32
+    /// Length of the release phase in seconds. A stolen voice fades out quickly
+    /// instead of cutting off abruptly.
+    fn release_time(&self, envelope: &Envelope) -> f32 {
+        if self.stolen {
+            STOLEN_VOICE_RELEASE
+        } else {
+            envelope.release
+        }
+    }
+
+    /// A released voice is finished once its release phase has run out.
+    fn is_finished(&self, envelope: &Envelope) -> bool {
+        !self.note_on && self.end_time as f32 >= self.release_time(envelope)
+    }
 }
 
-/// Midi note numbers are converted to a frequency value then adjusted for detuning, if any.
-fn midi_note_num_to_freq(midi_note_number: u8, detune: f32) -> f64 {
-    (((midi_note_number as f64 - 69.0) / 12.0).exp2() * 440.0) - detune as f64
+/// Seconds between the random targets the drift wanders towards.
+const DRIFT_INTERVAL: f64 = 0.5;
+
+/// Furthest the drift moves an oscillator's pitch, in cents.
+const DRIFT_CENTS: f32 = 8.0;
+
+/// Furthest the drift moves an oscillator's level, as a fraction of its volume.
+const DRIFT_LEVEL: f32 = 0.1;
+
+/// Slowly wandering offsets for the pitch and level of a voice's two
+/// oscillators, like the drift of an analog synth. Each voice has its own seed,
+/// so voices drift apart from each other but the same seed always drifts the same way.
+struct Drift {
+    state: u32,
+    counter: usize,
+    targets: [f32; 4],
+    values: [f32; 4],
 }
 
-/// Determines which phase of the ADS portion of the Envelope filter we are in
-/// and returns the amplitude at that point in time.
-/// This method is called when a key is pressed.
-fn get_amplitude(envelope: &Envelope, master_vol: f32) -> f32 {
-    if envelope.duration as f32 <= envelope.attack {
-        //attack phase
-       (envelope.duration as f32 / envelope.attack) * master_vol
-   } else if envelope.duration as f32 > envelope.attack && envelope.duration as f32 <= (envelope.attack + envelope.decay) {
-       // decay phase
-       ((envelope.duration as f32 - envelope.attack) / envelope.decay) * (envelope.sustain - master_vol) + master_vol
-   } else {
-       // sustain phase
-       envelope.sustain
-   }
+impl Drift {
+    fn new(seed: u32) -> Drift {
+        Drift {
+            state: seed.wrapping_mul(0x9E37_79B9) | 1,
+            counter: 0,
+            targets: [0.0; 4],
+            values: [0.0; 4],
+        }
+    }
+
+    /// Next value between -1 and 1 from a xorshift generator.
+    fn random(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+
+        self.state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    /// Moves the offsets a step towards their targets and returns them as
+    /// osc 1 pitch, osc 2 pitch, osc 1 level, and osc 2 level, each between -1 and 1.
+    fn next(&mut self, sample_rate: f64) -> [f32; 4] {
+        let interval = DRIFT_INTERVAL * sample_rate;
+        if self.counter == 0 {
+            for index in 0..4 {
+                self.targets[index] = self.random();
+            }
+            self.counter = interval as usize;
+        }
+        self.counter -= 1;
+
+        let coefficient = 1.0 - (-1.0 / interval).exp() as f32;
+        for (value, target) in self.values.iter_mut().zip(self.targets.iter()) {
+            *value += (target - *value) * coefficient;
+        }
+
+        self.values
+    }
 }
 
-/// Determines the amplitude during the Release phase of the Envelope filter.
-/// This method is called when a key is lifted.
-fn generate_release(envelope: &Envelope, master_vol: f32) -> f32 {
-    let mut release_amplitude = 0.0;
+/// Stores the settings and state of the resonant filter that is applied
+/// to the summed output. The state of each channel lives here so it carries
+/// over between buffers.
+struct Filter {
+    cutoff: f32,
+    resonance: f32,
+    filter_type: f32,
+    cutoff_mod: f32,
+    ic1eq: [f32; 2],
+    ic2eq: [f32; 2],
+}
 
-    if envelope.duration as f32 <= envelope.attack {
-        release_amplitude = (envelope.duration as f32 / envelope.attack) * master_vol;
+/// Default Filter values, a fully open low-pass with no resonance.
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            cutoff: 1.0,
+            resonance: 0.0,
+            filter_type: 0.0,
+            cutoff_mod: 0.0,
+            ic1eq: [0.0; 2],
+            ic2eq: [0.0; 2],
+        }
     }
-    if envelope.duration as f32 > envelope.attack && envelope.duration as f32 <= (envelope.attack + envelope.decay) {
-        release_amplitude = ((envelope.duration as f32 - envelope.attack) / envelope.decay) * (envelope.sustain - master_vol) + master_vol;
+}
+
+impl Filter {
+    /// Maps the 0 to 1 cutoff slider logarithmically onto 20 Hz to 20 kHz.
+    fn cutoff_freq(&self) -> f32 {
+        20.0 * 1000.0_f32.powf(self.cutoff)
     }
-    if envelope.duration as f32 > (envelope.attack + envelope.decay) {
-        release_amplitude = envelope.sustain;
+
+    /// The cutoff frequency after the filter envelope has moved the cutoff slider.
+    fn modulated_cutoff_freq(&self) -> f32 {
+        20.0 * 1000.0_f32.powf((self.cutoff + self.cutoff_mod).clamp(0.0, 1.0))
     }
 
-    (envelope.end_time as f32 / envelope.release) * (0.0 - release_amplitude) + release_amplitude
-}
+    /// How far the cutoff slider has to move to shift the cutoff by the given number of octaves.
+    fn octaves_to_cutoff(octaves: f32) -> f32 {
+        octaves / 1000.0_f32.log2()
+    }
 
-/// Basic distortion formula based on input signal and desired distortion level.
-/// Formula is based on
-/// https://ccrma.stanford.edu/~orchi/Documents/DAFx.pdf
-fn distortion(input: f32, dist: f32, dist_volume: f32) -> f32 {
-    // if dist_volume == 0.0 {
-    //     input
-    // } else {
-    //     dist_volume * ((input * (1.0 - (dist * (input).exp2() / input.abs()).exp())) / input.abs())
-    // }
+    /// Runs one sample of the channel through a state variable filter. The filter
+    /// type slider picks the low-pass, high-pass, or band-pass output.
+    /// Resonance is clamped short of self-oscillation so the filter can't blow up.
+    /// Formula is based on
+    /// https://cytomic.com/files/dsp/SvfLinearTrapOptimised2.pdf
+    fn process(&mut self, input: f32, channel: usize, sample_rate: f64) -> f32 {
+        let cutoff = self.modulated_cutoff_freq().min(sample_rate as f32 * 0.49);
+        let resonance = self.resonance.max(0.0).min(1.0);
+        let g = (PI as f32 * cutoff / sample_rate as f32).tan();
+        let k = 2.0 - 1.96 * resonance;
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
 
-    let gain = 5.0;
-    let q = input / input.abs();
-    let y = q * (1.0 - (gain * (q * input)).exp());
-    let z = dist * y + (1.0 - dist) * input;
+        let v3 = input - self.ic2eq[channel];
+        let v1 = a1 * self.ic1eq[channel] + a2 * v3;
+        let v2 = self.ic2eq[channel] + a2 * self.ic1eq[channel] + a3 * v3;
+        self.ic1eq[channel] = flush_denormal(2.0 * v1 - self.ic1eq[channel]);
+        self.ic2eq[channel] = flush_denormal(2.0 * v2 - self.ic2eq[channel]);
 
-    dist_volume * z
-}
+        if self.filter_type < 0.33 {
+            v2
+        } else if self.filter_type < 0.66 {
+            input - k * v1 - v2
+        } else {
+            v1
+        }
+    }
 
-/// Basic overdrive formula which is determined by the input signal.
-/// The overdrive has 3 phases which spits the input signal in thirds
-/// and generates a different output for each phase.
-/// Formula is based on
-/// https://ccrma.stanford.edu/~orchi/Documents/DAFx.pdf
-fn overdrive(input: f32) -> f32 {
-    if input == 0.0 {
-        input
-    } else {
-        let output: f32;
-        if input < 0.33 {
-            output = 2.0 * input;
-        } else if input >= 0.33 && input < 0.66 {
-            output = (3.0 - (2.0 - (3.0 * input)).exp2()) / 3.0;
-        } else if input >= 0.66 && input <= 1.0 {
-            output = 1.0;
+    /// Name of the filter type picked by the filter type slider.
+    fn type_name(&self) -> String {
+        if self.filter_type < 0.33 {
+            "Low-pass".to_string()
+        } else if self.filter_type < 0.66 {
+            "High-pass".to_string()
         } else {
-            output = input;
+            "Band-pass".to_string()
         }
+    }
+}
 
-        output
+/// Tempo in beats per minute used until the host reports one.
+const DEFAULT_TEMPO: f64 = 120.0;
+
+/// Time info flag that says the host filled in the tempo (kVstTempoValid in the VST SDK).
+const TEMPO_VALID: i32 = 1 << 10;
+
+/// Note values that the Lfo and delay can sync to, with their length in quarter note beats.
+const NOTE_DIVISIONS: [(&str, f64); 11] = [
+    ("1/1", 4.0),
+    ("1/2", 2.0),
+    ("1/4.", 1.5),
+    ("1/4", 1.0),
+    ("1/4T", 2.0 / 3.0),
+    ("1/8.", 0.75),
+    ("1/8", 0.5),
+    ("1/8T", 1.0 / 3.0),
+    ("1/16", 0.25),
+    ("1/16T", 1.0 / 6.0),
+    ("1/32", 0.125),
+];
+
+/// Maps a sync slider onto one of the note divisions. The bottom of the slider
+/// turns sync off.
+fn sync_division(val: f32) -> Option<usize> {
+    match (val * NOTE_DIVISIONS.len() as f32).round() as usize {
+        0 => None,
+        step => Some(step.min(NOTE_DIVISIONS.len()) - 1),
     }
 }
 
-/// Creates brownian noise based on random f32 values.
-fn noise(dist: f32) -> f32 {
-    dist * (((0.02 * (random::<f32>() * 2.0 - 1.0)) / 1.02) * 3.5)
+/// The note division picked by a sync slider, or Off.
+fn sync_text(val: f32) -> String {
+    match sync_division(val) {
+        Some(division) => NOTE_DIVISIONS[division].0.to_string(),
+        None => "Off".to_string(),
+    }
 }
 
-/// Handles incomming midi message data and determines whether to start or
-/// stop a particular note.
-/// See https://www.midi.org/specifications-old/item/table-1-summary-of-midi-message
-impl Crust {
-    fn process_midi_data(&mut self, midi_data: [u8; 3]) {
-        match midi_data[0] {
-            128 => self.note_off(midi_data[1]),
-            144 => self.note_on(midi_data[1]),
-            // 224 => self.pitch_bend(midi_data[1]),
-            _ => (),
+/// Length in samples of a note division of the given number of beats at the tempo.
+fn division_to_samples(beats: f64, tempo: f64, sample_rate: f64) -> f64 {
+    beats * 60.0 / tempo * sample_rate
+}
+
+/// Stores the settings and phase of the low frequency oscillator, which can be
+/// routed to pitch for vibrato or to amplitude for tremolo.
+struct Lfo {
+    rate: f32,
+    depth: f32,
+    wave_index: f32,
+    destination: f32,
+    retrigger: f32,
+    sync: f32,
+    phase: f64,
+}
+
+/// Default Lfo values, which leave the sound unmodulated.
+impl Default for Lfo {
+    fn default() -> Lfo {
+        Lfo {
+            rate: 0.5,
+            depth: 0.0,
+            wave_index: 0.0,
+            destination: 0.0,
+            retrigger: 0.0,
+            sync: 0.0,
+            phase: 0.0,
         }
     }
+}
 
-    /// Assigns each oscillator a midi note number.
-    /// Starts the duration timer for the envelope filter.
-    /// Adds note to vector of active notes.
-    fn note_on(&mut self, note: u8) {
-        self.notes.push(note);
-        self.envelope.note_on = true;
-        self.envelope.duration = 0.0;
+impl Lfo {
+    /// Maps the 0 to 1 rate slider logarithmically onto 0.1 Hz to 20 Hz.
+    fn rate_hz(&self) -> f32 {
+        0.1 * 200.0_f32.powf(self.rate)
     }
 
-    /// Stops the duration timer for the envelope filter.
-    /// Reomves note from active note vector.
-    fn note_off(&mut self, note: u8) {
-        self.notes.retain(|&x| x != note);
-        self.envelope.note_on = false;
-        self.envelope.end_time = 0.0;
+    /// The lower half of the destination slider routes the Lfo to pitch,
+    /// the upper half routes it to amplitude.
+    fn modulates_pitch(&self) -> bool {
+        self.destination < 0.5
     }
-}
 
-/// Implements all methods required for the Plugin trait of the vst crate.
-impl Plugin for Crust {
-    fn get_info(&self) -> Info {
-        Info {
-            name: "Crust".to_string(),
-            unique_id: 736251,
-            inputs: 2,
-            outputs: 2,
-            parameters: 12,
-            category: Category::Synth,
-            ..Default::default()
+    /// Returns the current Lfo value between -1 and 1, using the sine, triangle,
+    /// or square shape picked by the waveform slider.
+    fn value(&self) -> f32 {
+        if self.wave_index < 0.33 {
+            create_sine_wave_from_phase(self.phase, 1.0)
+        } else if self.wave_index < 0.66 {
+            create_triangle_wave_from_phase(self.phase, 1.0)
+        } else if self.phase < 0.5 {
+            1.0
+        } else {
+            -1.0
         }
     }
 
-    /// Gets the values that will be used in the plugin UI in the DAW.
-    fn get_parameter(&self, index: i32) -> f32 {
-        match index {
-            0 => self.oscillators[0].wave_index,
-            1 => self.oscillators[0].volume,
-            2 => self.oscillators[0].detune,
-            3 => self.oscillators[1].wave_index,
-            4 => self.oscillators[1].volume,
-            5 => self.oscillators[1].detune,
-            6 => self.noise,
-            7 => self.envelope.attack,
-            8 => self.envelope.decay,
-            9 => self.envelope.sustain,
-            10 => self.envelope.release,
-            11 => self.master_vol,
-            _ => 0.0,
+    /// Name of the shape picked by the waveform slider.
+    fn waveform_name(&self) -> &'static str {
+        if self.wave_index < 0.33 {
+            "Sine"
+        } else if self.wave_index < 0.66 {
+            "Triangle"
+        } else {
+            "Square"
         }
     }
 
-    /// Sets each value based on slider values in UI in the DAW.
-    fn set_parameter(&mut self, index: i32, val: f32) {
-        match index {
-            0 => self.oscillators[0].wave_index = val,
-            1 => self.oscillators[0].volume = val,
-            2 => self.oscillators[0].detune = val * 10.0,
-            3 => self.oscillators[1].wave_index = val,
-            4 => self.oscillators[1].volume = val,
-            5 => self.oscillators[1].detune = val * 10.0,
-            6 => self.noise = val,
-            7 => self.envelope.attack = val * 5.0,
-            8 => self.envelope.decay = val * 5.0,
-            9 => self.envelope.sustain = val,
-            10 => self.envelope.release = val * 5.0,
-            11 => self.master_vol = val,
-            _ => (),
+    /// Pitch offset in semitones, up to a whole tone either way at full depth.
+    fn pitch_offset(&self) -> f32 {
+        if self.modulates_pitch() {
+            self.depth * self.value() * 2.0
+        } else {
+            0.0
         }
     }
 
-    /// The text that will appear under each slider in the UI.
-    fn get_parameter_name(&self, index: i32) -> String {
-        match index {
-            0 => "Osc 1 waveform".to_string(),
-            1 => "Osc 1 volume".to_string(),
-            2 => "Osc 1 detune".to_string(),
-            3 => "Osc 2 waveform".to_string(),
-            4 => "Osc 2 volume".to_string(),
-            5 => "Osc 2 detune".to_string(),
-            6 => "Noise".to_string(),
-            7 => "Attack".to_string(),
-            8 => "Decay".to_string(),
-            9 => "Sustain".to_string(),
-            10 => "Release".to_string(),
-            11 => "Master volume".to_string(),
-            _ => "".to_string(),
+    /// Gain applied to the output, swinging between 1 and 1 - depth.
+    fn amplitude(&self) -> f32 {
+        if self.modulates_pitch() {
+            1.0
+        } else {
+            1.0 - self.depth * (0.5 - 0.5 * self.value())
         }
     }
 
-    /// Determines how to display the data based on the slider position in the UI.
-    fn get_parameter_text(&self, index: i32) -> String {
-        match index {
-            0 => format!("{}", (self.oscillators[0].wave_index * 3.0).round()),
-            1 => format!("{}%", (self.oscillators[0].volume * 100.0).round()),
-            2 => format!("{}", self.oscillators[0].detune),
-            3 => format!("{}", (self.oscillators[0].wave_index * 3.0).round()),
-            4 => format!("{}%", (self.oscillators[1].volume * 100.0).round()),
-            5 => format!("{}", self.oscillators[1].detune),
-            6 => format!("{}%", (self.noise * 100.0).round()),
-            7 => format!("{}", self.envelope.attack),
-            8 => format!("{}", self.envelope.decay),
-            9 => format!("{}", self.envelope.sustain),
-            10 => format!("{}", self.envelope.release),
-            11 => format!("{}%", (self.master_vol* 100.0).round()),
-            _ => "".to_string(),
-        }
+    /// The upper half of the retrigger slider restarts the Lfo on every note,
+    /// the lower half leaves it free running.
+    fn retriggers(&self) -> bool {
+        self.retrigger >= 0.5
     }
 
-    /// Entery point of the program.
-    /// Handles incoming midi events.
-    fn process_events(&mut self, events: &Events) {
-        for event in events.events() {
-            match event {
-                Event::Midi(ev) => self.process_midi_data(ev.data),
-                _ => (),
-            }
+    /// Lfo frequency in Hz, which is one cycle per note division when synced to the tempo.
+    fn frequency(&self, tempo: f64) -> f64 {
+        match sync_division(self.sync) {
+            Some(division) => tempo / 60.0 / NOTE_DIVISIONS[division].1,
+            None => self.rate_hz() as f64,
         }
     }
 
-    /// Method for outputting audio.
-    /// Loops through the buffer and outputs an f32 value between 0 and 1
-    /// for each sample.
-    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        let samples = buffer.samples();
-        let sample = (1.0 / self.sample_rate) as f64;
-
-        for (input_buffer, output_buffer) in buffer.zip() {
-            let mut time = self.time;
-
-            for (_, output_sample) in input_buffer.iter().zip(output_buffer) {
-                let mut wave1 = 0.0;
-                let mut wave2 = 0.0;
-                let mut osc1_volume = self.oscillators[0].volume;
-                let mut osc2_volume = self.oscillators[1].volume;
-
-                for i in 0..self.notes.len() {
-
-                    // Build oscillator 1 wave.
-                    if self.oscillators[0].wave_index >= 0.0 && self.oscillators[0].wave_index < 0.33 {
-                        wave1 += create_sine_wave(self.notes[i], osc1_volume, time, self.oscillators[0].detune);
-                    } else if self.oscillators[0].wave_index >= 0.33 && self.oscillators[0].wave_index < 0.66 {
-                        wave1 += create_sawtooth_wave(self.notes[i], osc1_volume, time, self.oscillators[0].detune);
-                    } else if self.oscillators[0].wave_index >= 0.66 && self.oscillators[0].wave_index < 1.0 {
-                        wave1 += create_square_wave(self.notes[i], osc1_volume, time, self.oscillators[0].detune);
-                    } else if self.oscillators[0].wave_index >= 1.0 {
-                         wave1 += create_triangle_wave(self.notes[i], osc1_volume, time, self.oscillators[0].detune);
-                    } else {
-                         wave1 = 0.0;
-                    }
-
-                    // Build oscillator 2 wave.
-                    if self.oscillators[1].wave_index >= 0.0 && self.oscillators[1].wave_index < 0.33 {
-                        wave2 += create_sine_wave(self.notes[i], osc2_volume, time, self.oscillators[1].detune);
-                    } else if self.oscillators[1].wave_index >= 0.33 && self.oscillators[1].wave_index < 0.66 {
-                        wave2 += create_sawtooth_wave(self.notes[i], osc2_volume, time, self.oscillators[1].detune);
-                    } else if self.oscillators[1].wave_index >= 0.66 && self.oscillators[1].wave_index < 1.0 {
-                        wave2 += create_square_wave(self.notes[i], osc2_volume, time, self.oscillators[1].detune);
-                    } else if self.oscillators[1].wave_index >= 1.0 {
-                         wave2 += create_triangle_wave(self.notes[i], osc2_volume, time, self.oscillators[1].detune);
-                    } else {
-                         wave2 = 0.0;
-                    }
-                } // end of notes vec loop
-
-                // Apply envelope filter.
-                if self.envelope.note_on == true {
-                    *output_sample = get_amplitude(&self.envelope, self.master_vol) as f32 * (wave1 + wave2 + noise(self.noise));
-
-                    self.envelope.duration += sample;
-                } else {
-                    let mut release_volume = generate_release(&self.envelope, self.master_vol);
+    /// Moves the Lfo forward by one sample period.
+    fn advance(&mut self, sample: f64, tempo: f64) {
+        self.phase = advance_phase(self.phase, self.frequency(tempo) * sample);
+    }
+}
 
-                    if release_volume < 0.0 {
-                        *output_sample = 0.0;
-                    } else {
-                        *output_sample = release_volume * (wave1 + wave2 + noise(self.noise));
-                    }
+/// Stores the noise color setting and the filter state used to color white noise.
+struct NoiseGenerator {
+    color: f32,
+    brown: f32,
+    pink: [f32; 7],
+    rng: SmallRng,
+}
 
-                    self.envelope.end_time += sample;
-                }
-                time += sample;
-            } // end of sample loop
+/// Default NoiseGenerator values, brown noise from a random seed.
+impl Default for NoiseGenerator {
+    fn default() -> NoiseGenerator {
+        NoiseGenerator {
+            color: 1.0,
+            brown: 0.0,
+            pink: [0.0; 7],
+            rng: noise_rng(random::<u64>()),
+        }
+    }
+}
+
+/// Random number generator for the noise, which always gives the same
+/// numbers for the same seed.
+fn noise_rng(seed: u64) -> SmallRng {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes[8..].copy_from_slice(&(!seed).to_le_bytes());
+
+    SmallRng::from_seed(bytes)
+}
+
+impl NoiseGenerator {
+    /// Restarts the noise from a seed, so it plays back the same every time.
+    fn seed(&mut self, seed: u64) {
+        self.rng = noise_rng(seed);
+        self.brown = 0.0;
+        self.pink = [0.0; 7];
+    }
+
+    /// Name of the noise color picked by the color slider.
+    fn color_name(&self) -> String {
+        if self.color < 0.33 {
+            "White".to_string()
+        } else if self.color < 0.66 {
+            "Pink".to_string()
+        } else {
+            "Brown".to_string()
+        }
+    }
+
+    /// Returns the next noise sample scaled by the noise level, in the white,
+    /// pink, or brown color picked by the color slider.
+    /// Pink noise uses Paul Kellett's filter, see
+    /// http://www.firstpr.com.au/dsp/pink-noise/
+    fn next(&mut self, dist: f32) -> f32 {
+        let white = self.rng.gen::<f32>() * 2.0 - 1.0;
+
+        let value = if self.color < 0.33 {
+            white
+        } else if self.color < 0.66 {
+            let b = &mut self.pink;
+            b[0] = 0.99886 * b[0] + white * 0.0555179;
+            b[1] = 0.99332 * b[1] + white * 0.0750759;
+            b[2] = 0.96900 * b[2] + white * 0.153852;
+            b[3] = 0.86650 * b[3] + white * 0.3104856;
+            b[4] = 0.55000 * b[4] + white * 0.5329522;
+            b[5] = -0.7616 * b[5] - white * 0.0168980;
+            let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+            b[6] = white * 0.115926;
+            pink * 0.11
+        } else {
+            self.brown = (self.brown + 0.02 * white) / 1.02;
+            self.brown * 3.5
+        };
+
+        dist * value
+    }
+}
+
+/// Stores the settings and state of the sample and hold modulation source.
+/// At every step it takes a new value from its own pink noise and holds it
+/// until the next step, so the steps wander rather than jump about evenly.
+struct SampleHold {
+    rate: f32,
+    depth: f32,
+    destination: f32,
+    phase: f64,
+    value: f32,
+    noise: NoiseGenerator,
+}
+
+/// Default SampleHold values, which leave the sound unmodulated.
+impl Default for SampleHold {
+    fn default() -> SampleHold {
+        SampleHold {
+            rate: 0.5,
+            depth: 0.0,
+            destination: 0.0,
+            phase: 0.0,
+            value: 0.0,
+            noise: NoiseGenerator { color: 0.5, ..Default::default() },
+        }
+    }
+}
+
+impl SampleHold {
+    /// Maps the 0 to 1 rate slider logarithmically onto 0.1 Hz to 20 Hz steps.
+    fn rate_hz(&self) -> f32 {
+        0.1 * 200.0_f32.powf(self.rate)
+    }
+
+    /// The lower half of the destination slider routes the steps to pitch,
+    /// the upper half routes them to the filter cutoff.
+    fn modulates_pitch(&self) -> bool {
+        self.destination < 0.5
+    }
+
+    /// Pitch offset in semitones, up to an octave either way at full depth.
+    fn pitch_offset(&self) -> f32 {
+        if self.modulates_pitch() {
+            self.depth * self.value * 12.0
+        } else {
+            0.0
+        }
+    }
+
+    /// Cutoff offset, up to four octaves either way at full depth.
+    fn cutoff_offset(&self) -> f32 {
+        if self.modulates_pitch() {
+            0.0
+        } else {
+            Filter::octaves_to_cutoff(self.depth * self.value * 4.0)
+        }
+    }
+
+    /// Moves forward by one sample period, picking up a new value whenever a step ends.
+    fn advance(&mut self, sample: f64) {
+        let phase = self.phase + self.rate_hz() as f64 * sample;
+        if phase >= 1.0 {
+            self.value = self.noise.next(1.0).clamp(-1.0, 1.0);
+        }
+        self.phase = phase % 1.0;
+    }
+}
+
+/// Longest delay time in seconds, which sets the size of the delay buffer.
+const MAX_DELAY_TIME: f64 = 2.0;
+
+/// Shortest delay of the chorus taps in seconds.
+const CHORUS_BASE_DELAY: f64 = 0.015;
+
+/// Furthest the chorus Lfo can push a tap past the base delay, in seconds.
+const CHORUS_MAX_DEPTH: f64 = 0.01;
+
+/// Number of modulated taps the chorus mixes together.
+const CHORUS_VOICES: usize = 3;
+
+/// A circular buffer of past stereo frames that can be read back at any delay.
+struct DelayLine {
+    buffer: Vec<[f32; 2]>,
+    position: usize,
+}
+
+impl DelayLine {
+    /// Creates a silent delay line that holds the given number of seconds at the sample rate.
+    fn new(seconds: f64, sample_rate: f64) -> DelayLine {
+        DelayLine {
+            buffer: vec![[0.0; 2]; (seconds * sample_rate) as usize + 1],
+            position: 0,
+        }
+    }
+
+    /// Reads the frame from the given number of samples ago. Fractional delays
+    /// are linearly interpolated between the two nearest frames.
+    fn read(&self, delay_samples: f64) -> [f32; 2] {
+        let length = self.buffer.len();
+        let delay_samples = delay_samples.clamp(1.0, (length - 1) as f64);
+        let whole = delay_samples.floor() as usize;
+        let fraction = (delay_samples - whole as f64) as f32;
+        let newer = self.buffer[(self.position + length - whole) % length];
+        let older = self.buffer[(self.position + 2 * length - whole - 1) % length];
+
+        [
+            newer[0] + (older[0] - newer[0]) * fraction,
+            newer[1] + (older[1] - newer[1]) * fraction,
+        ]
+    }
+
+    /// Stores a frame and moves on to the next slot in the buffer.
+    fn write(&mut self, frame: [f32; 2]) {
+        self.buffer[self.position] = frame;
+        self.position = (self.position + 1) % self.buffer.len();
+    }
+}
+
+/// Stores the settings of the delay effect along with its buffer of past stereo output.
+struct Delay {
+    time: f32,
+    sync: f32,
+    feedback: f32,
+    mix: f32,
+    line: DelayLine,
+}
+
+/// Default Delay values, a quarter second echo that is mixed out.
+impl Default for Delay {
+    fn default() -> Delay {
+        Delay {
+            time: 0.25,
+            sync: 0.0,
+            feedback: 0.3,
+            mix: 0.0,
+            line: DelayLine::new(MAX_DELAY_TIME, 44100.0),
+        }
+    }
+}
+
+impl Delay {
+    /// Reallocates the buffer so it holds MAX_DELAY_TIME seconds at the sample rate.
+    fn resize(&mut self, sample_rate: f64) {
+        self.line = DelayLine::new(MAX_DELAY_TIME, sample_rate);
+    }
+
+    /// Delay in samples, which is a note division long when synced to the tempo.
+    fn delay_samples(&self, sample_rate: f64, tempo: f64) -> f64 {
+        match sync_division(self.sync) {
+            Some(division) => division_to_samples(NOTE_DIVISIONS[division].1, tempo, sample_rate).round(),
+            None => (self.time as f64 * sample_rate).round(),
+        }
+    }
+
+    /// Samples until the echoes have died down below -60 dB. Every repeat is
+    /// the feedback times quieter than the one before it.
+    fn tail_samples(&self, sample_rate: f64, tempo: f64) -> f64 {
+        if self.mix == 0.0 {
+            return 0.0;
+        }
+
+        let feedback = self.feedback.clamp(0.0, 0.95) as f64;
+        let repeats = if feedback > 0.0 {
+            1.0 + (0.001_f64.ln() / feedback.ln()).ceil()
+        } else {
+            1.0
+        };
+
+        self.delay_samples(sample_rate, tempo) * repeats
+    }
+
+    /// Writes a stereo frame into the buffer and returns it mixed with the frame
+    /// from delay time ago. Feedback is clamped below 1 so the echoes always die out.
+    fn process(&mut self, input: [f32; 2], sample_rate: f64, tempo: f64) -> [f32; 2] {
+        let delayed = self.line.read(self.delay_samples(sample_rate, tempo));
+        let feedback = self.feedback.clamp(0.0, 0.95);
+        let mut output = [0.0; 2];
+        let mut frame = [0.0; 2];
+
+        for channel in 0..2 {
+            frame[channel] = flush_denormal(input[channel] + delayed[channel] * feedback);
+            output[channel] = input[channel] * (1.0 - self.mix) + delayed[channel] * self.mix;
+        }
+        self.line.write(frame);
+
+        output
+    }
+}
+
+/// Stores the settings of the chorus effect, its Lfo phase, and the buffer its taps read from.
+struct Chorus {
+    rate: f32,
+    depth: f32,
+    mix: f32,
+    phase: f64,
+    line: DelayLine,
+}
+
+/// Default Chorus values, a slow and moderate chorus that is mixed out.
+impl Default for Chorus {
+    fn default() -> Chorus {
+        Chorus {
+            rate: 0.3,
+            depth: 0.5,
+            mix: 0.0,
+            phase: 0.0,
+            line: DelayLine::new(CHORUS_BASE_DELAY + CHORUS_MAX_DEPTH, 44100.0),
+        }
+    }
+}
+
+impl Chorus {
+    /// Reallocates the buffer so it holds the longest tap delay at the sample rate.
+    fn resize(&mut self, sample_rate: f64) {
+        self.line = DelayLine::new(CHORUS_BASE_DELAY + CHORUS_MAX_DEPTH, sample_rate);
+    }
+
+    /// Maps the 0 to 1 rate slider logarithmically onto 0.1 Hz to 5 Hz.
+    fn rate_hz(&self) -> f32 {
+        0.1 * 50.0_f32.powf(self.rate)
+    }
+
+    /// Delay in samples of a chorus tap. Each tap follows the Lfo a third of a
+    /// cycle apart, and the right channel runs a quarter cycle behind the left.
+    fn tap_delay(&self, voice: usize, channel: usize, sample_rate: f64) -> f64 {
+        let phase = self.phase + voice as f64 / CHORUS_VOICES as f64 + channel as f64 * 0.25;
+        let sweep = 0.5 + 0.5 * (2.0 * PI * phase).sin();
+
+        (CHORUS_BASE_DELAY + self.depth as f64 * CHORUS_MAX_DEPTH * sweep) * sample_rate
+    }
+
+    /// Writes a stereo frame into the buffer and returns it mixed with the modulated taps.
+    fn process(&mut self, input: [f32; 2], sample_rate: f64) -> [f32; 2] {
+        let mut output = [0.0; 2];
+
+        for (channel, value) in output.iter_mut().enumerate() {
+            let mut wet = 0.0;
+            for voice in 0..CHORUS_VOICES {
+                wet += self.line.read(self.tap_delay(voice, channel, sample_rate))[channel];
+            }
+            wet /= CHORUS_VOICES as f32;
+            *value = input[channel] * (1.0 - self.mix) + wet * self.mix;
+        }
+        self.line.write(input);
+        self.phase = advance_phase(self.phase, self.rate_hz() as f64 / sample_rate);
+
+        output
+    }
+}
+
+/// Stores the bitcrusher settings and the frame it is holding while it skips samples.
+struct Bitcrusher {
+    bit_depth: f32,
+    downsample: f32,
+    held: [f32; 2],
+    counter: usize,
+}
+
+/// Default Bitcrusher values, full resolution at the full sample rate.
+impl Default for Bitcrusher {
+    fn default() -> Bitcrusher {
+        Bitcrusher {
+            bit_depth: 1.0,
+            downsample: 0.0,
+            held: [0.0; 2],
+            counter: 0,
+        }
+    }
+}
+
+impl Bitcrusher {
+    /// Maps the bit depth slider onto 1 to 16 bits.
+    fn bits(&self) -> u32 {
+        1 + (self.bit_depth * 15.0).round() as u32
+    }
+
+    /// Maps the downsample slider onto holding each sample for 1 to 64 samples.
+    fn factor(&self) -> usize {
+        1 + (self.downsample * 63.0).round() as usize
+    }
+
+    /// Rounds the input down onto one of 2 ^ bits evenly spaced levels between
+    /// -1 and 1. At 16 bits the input is left untouched.
+    fn quantize(&self, input: f32) -> f32 {
+        let bits = self.bits();
+        if bits >= 16 {
+            return input;
+        }
+
+        let half = (1 << (bits - 1)) as f32;
+        let level = (input * half).floor().clamp(-half, half - 1.0);
+
+        (level + 0.5) / half
+    }
+
+    /// Quantizes a new frame every factor samples and repeats it in between.
+    fn process(&mut self, input: [f32; 2]) -> [f32; 2] {
+        if self.counter == 0 {
+            self.held = [self.quantize(input[0]), self.quantize(input[1])];
+        }
+        self.counter = (self.counter + 1) % self.factor();
+
+        self.held
+    }
+}
+
+/// Comb filter lengths of the reverb in samples at 44.1 kHz, from Freeverb.
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+
+/// Allpass filter lengths of the reverb in samples at 44.1 kHz, from Freeverb.
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+
+/// The right channel's filters are this many samples longer so the two sides decorrelate.
+const REVERB_STEREO_SPREAD: usize = 23;
+
+/// Feedback comb filter with a low-pass in its loop, which makes the high end
+/// of the reverb die away faster than the lows.
+struct Comb {
+    buffer: Vec<f32>,
+    position: usize,
+    filter_store: f32,
+}
+
+impl Comb {
+    fn new(length: usize) -> Comb {
+        Comb {
+            buffer: vec![0.0; length.max(1)],
+            position: 0,
+            filter_store: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.position];
+        self.filter_store = flush_denormal(output * (1.0 - damping) + self.filter_store * damping);
+        self.buffer[self.position] = flush_denormal(input + self.filter_store * feedback);
+        self.position = (self.position + 1) % self.buffer.len();
+
+        output
+    }
+}
+
+/// Allpass filter that smears the comb echoes into a dense tail without colouring it.
+struct Allpass {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl Allpass {
+    fn new(length: usize) -> Allpass {
+        Allpass {
+            buffer: vec![0.0; length.max(1)],
+            position: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.position];
+        self.buffer[self.position] = flush_denormal(input + buffered * 0.5);
+        self.position = (self.position + 1) % self.buffer.len();
+
+        buffered - input
+    }
+}
+
+/// Stores the reverb settings and the comb and allpass filters of each channel.
+/// The reverb is Freeverb: parallel combs feeding a chain of allpasses.
+/// See https://ccrma.stanford.edu/~jos/pasp/Freeverb.html
+struct Reverb {
+    room_size: f32,
+    damping: f32,
+    mix: f32,
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+}
+
+/// Default Reverb values, a medium room that is mixed out.
+impl Default for Reverb {
+    fn default() -> Reverb {
+        let mut reverb = Reverb {
+            room_size: 0.5,
+            damping: 0.5,
+            mix: 0.0,
+            combs: [Vec::new(), Vec::new()],
+            allpasses: [Vec::new(), Vec::new()],
+        };
+        reverb.resize(44100.0);
+
+        reverb
+    }
+}
+
+impl Reverb {
+    /// Rebuilds the filters with their lengths scaled to the sample rate, so
+    /// the room sounds the same at any rate.
+    fn resize(&mut self, sample_rate: f64) {
+        let scale = sample_rate / 44100.0;
+        for channel in 0..2 {
+            let spread = channel * REVERB_STEREO_SPREAD;
+            self.combs[channel] = COMB_TUNING.iter()
+                .map(|&length| Comb::new(((length + spread) as f64 * scale) as usize))
+                .collect();
+            self.allpasses[channel] = ALLPASS_TUNING.iter()
+                .map(|&length| Allpass::new(((length + spread) as f64 * scale) as usize))
+                .collect();
+        }
+    }
+
+    /// Comb feedback for the room size slider. Bigger rooms ring for longer.
+    fn feedback(&self) -> f32 {
+        0.7 + self.room_size * 0.28
+    }
+
+    /// Samples until the longest comb has died down below -60 dB.
+    fn tail_samples(&self) -> f64 {
+        if self.mix == 0.0 {
+            return 0.0;
+        }
+
+        let longest = self.combs[1].iter().map(|comb| comb.buffer.len()).max().unwrap_or(0);
+        longest as f64 * (0.001_f64.ln() / (self.feedback() as f64).ln()).ceil()
+    }
+
+    /// Runs a stereo frame through the reverb and mixes the reverb with it.
+    /// Both channels feed the same mono input into the filters.
+    fn process(&mut self, input: [f32; 2]) -> [f32; 2] {
+        let feedback = self.feedback();
+        let damping = self.damping * 0.4;
+        let mono = (input[0] + input[1]) * 0.015;
+        let mut output = [0.0; 2];
+
+        for (channel, value) in output.iter_mut().enumerate() {
+            let mut wet: f32 = self.combs[channel].iter_mut()
+                .map(|comb| comb.process(mono, feedback, damping))
+                .sum();
+            for allpass in self.allpasses[channel].iter_mut() {
+                wet = allpass.process(wet);
+            }
+
+            *value = input[channel] * (1.0 - self.mix) + wet * 3.0 * self.mix;
+        }
+
+        output
+    }
+}
+
+/// Time constant in seconds of the level estimates used by the auto gain.
+const AUTO_GAIN_TIME: f64 = 0.05;
+
+/// Keeps running estimates of the power going into and coming out of the
+/// saturation stage, so its output can be turned back down to the input level.
+struct AutoGain {
+    input_power: f32,
+    output_power: f32,
+}
+
+impl Default for AutoGain {
+    fn default() -> AutoGain {
+        AutoGain {
+            input_power: 0.0,
+            output_power: 0.0,
+        }
+    }
+}
+
+impl AutoGain {
+    /// Updates the estimates with a stereo frame before and after saturation and
+    /// returns the gain that matches their RMS levels. Silence gets a gain of 1.
+    fn update(&mut self, input: [f32; 2], output: [f32; 2], sample_rate: f64) -> f32 {
+        let coefficient = (1.0 - (-1.0 / (AUTO_GAIN_TIME * sample_rate)).exp()) as f32;
+        let power = |frame: [f32; 2]| (frame[0] * frame[0] + frame[1] * frame[1]) / 2.0;
+        self.input_power = flush_denormal(self.input_power + (power(input) - self.input_power) * coefficient);
+        self.output_power = flush_denormal(self.output_power + (power(output) - self.output_power) * coefficient);
+
+        if self.output_power == 0.0 {
+            1.0
+        } else {
+            (self.input_power / self.output_power).sqrt().min(4.0)
+        }
+    }
+}
+
+/// How far ahead the limiter looks in samples, which is also the delay it adds.
+/// It is a fixed number of samples because hosts only read the latency when
+/// the plugin is loaded, so it can't follow the sample rate.
+const LIMITER_LOOKAHEAD: usize = 88;
+
+/// Brickwall limiter on the master output. The output is delayed by the
+/// lookahead, so the gain can ramp down ahead of a peak instead of clipping it.
+/// The delay stays in while the limiter is off, so the latency never changes.
+struct Limiter {
+    enabled: f32,
+    threshold: f32,
+    release: f32,
+    line: Vec<[f32; 2]>,
+    gains: Vec<f32>,
+    position: usize,
+    gain: f32,
+}
+
+/// Default Limiter values, switched off with the threshold at 0 dB.
+impl Default for Limiter {
+    fn default() -> Limiter {
+        Limiter {
+            enabled: 0.0,
+            threshold: 1.0,
+            release: 0.5,
+            line: vec![[0.0; 2]; LIMITER_LOOKAHEAD],
+            gains: vec![1.0; LIMITER_LOOKAHEAD],
+            position: 0,
+            gain: 1.0,
+        }
+    }
+}
+
+impl Limiter {
+    /// Empties the lookahead delay and lets go of any gain reduction.
+    fn reset(&mut self) {
+        self.line.iter_mut().for_each(|frame| *frame = [0.0; 2]);
+        self.gains.iter_mut().for_each(|gain| *gain = 1.0);
+        self.position = 0;
+        self.gain = 1.0;
+    }
+
+    fn is_on(&self) -> bool {
+        self.enabled >= 0.5
+    }
+
+    /// Samples of delay the lookahead adds to the output.
+    fn latency(&self) -> usize {
+        self.line.len()
+    }
+
+    /// Maps the threshold slider onto -24 dB to 0 dB.
+    fn threshold_db(&self) -> f32 {
+        (self.threshold - 1.0) * 24.0
+    }
+
+    /// Maps the release slider logarithmically onto 10 ms to 1 s.
+    fn release_time(&self) -> f32 {
+        0.01 * 100.0_f32.powf(self.release)
+    }
+
+    /// Takes in a frame and returns the frame from the lookahead ago, turned
+    /// down so it stays under the threshold. The gain ramps down in a straight
+    /// line to reach what each frame needs by the time that frame comes out,
+    /// and recovers with the release time once the peaks have passed. While
+    /// switched off nothing is turned down and the frames are only delayed.
+    fn process(&mut self, input: [f32; 2], sample_rate: f64) -> [f32; 2] {
+        let length = self.line.len();
+        let output = self.line[self.position];
+        if !self.is_on() {
+            self.line[self.position] = input;
+            self.gains[self.position] = 1.0;
+            self.gain = 1.0;
+            self.position = (self.position + 1) % length;
+            return output;
+        }
+
+        let threshold = 10.0_f32.powf(self.threshold_db() / 20.0);
+        let peak = input[0].abs().max(input[1].abs());
+        let leaving = self.gains[self.position];
+        self.line[self.position] = input;
+        self.gains[self.position] = if peak > threshold { threshold / peak } else { 1.0 };
+
+        let release = (1.0 - (-1.0 / (self.release_time() as f64 * sample_rate)).exp()) as f32;
+        let mut gain = (self.gain + (1.0 - self.gain) * release).min(leaving);
+        for age in 0..length {
+            let needed = self.gains[(self.position + length - age) % length];
+            let steps = (length - age) as f32;
+            gain = gain.min(self.gain + (needed - self.gain) / (steps + 1.0));
+        }
+        self.gain = gain;
+        self.position = (self.position + 1) % length;
+
+        [output[0] * gain, output[1] * gain]
+    }
+}
+
+/// Cutoff in Hz of the high-pass that removes DC offset from the output.
+const DC_BLOCKER_CUTOFF: f64 = 10.0;
+
+/// One pole high-pass that takes any DC offset out of the output, such as the
+/// offset of a narrow pulse wave or an uneven distortion.
+struct DcBlocker {
+    enabled: f32,
+    previous_input: [f32; 2],
+    previous_output: [f32; 2],
+}
+
+/// Default DcBlocker values, switched off.
+impl Default for DcBlocker {
+    fn default() -> DcBlocker {
+        DcBlocker {
+            enabled: 0.0,
+            previous_input: [0.0; 2],
+            previous_output: [0.0; 2],
+        }
+    }
+}
+
+impl DcBlocker {
+    fn is_on(&self) -> bool {
+        self.enabled >= 0.5
+    }
+
+    fn process(&mut self, input: [f32; 2], sample_rate: f64) -> [f32; 2] {
+        let pole = (-2.0 * PI * DC_BLOCKER_CUTOFF / sample_rate).exp() as f32;
+        let mut output = [0.0; 2];
+        for channel in 0..2 {
+            output[channel] = flush_denormal(input[channel] - self.previous_input[channel] + pole * self.previous_output[channel]);
+            self.previous_input[channel] = input[channel];
+            self.previous_output[channel] = output[channel];
+        }
+
+        output
+    }
+}
+
+/// Time constant in seconds that the output meter falls back with.
+const METER_RELEASE: f64 = 0.3;
+
+/// Follows the peak and RMS level of the output for displaying on a meter.
+/// The peak jumps straight up and both levels fall back smoothly.
+struct Meter {
+    peak: f32,
+    power: f32,
+}
+
+impl Default for Meter {
+    fn default() -> Meter {
+        Meter {
+            peak: 0.0,
+            power: 0.0,
+        }
+    }
+}
+
+impl Meter {
+    /// Runs a buffer of stereo frames through the meter. The levels are taken
+    /// across both channels.
+    fn update(&mut self, frames: &[[f32; 2]], sample_rate: f64) {
+        let coefficient = (1.0 - (-1.0 / (METER_RELEASE * sample_rate)).exp()) as f32;
+        for frame in frames {
+            let peak = frame[0].abs().max(frame[1].abs());
+            let power = (frame[0] * frame[0] + frame[1] * frame[1]) / 2.0;
+            self.peak = flush_denormal(peak.max(self.peak * (1.0 - coefficient)));
+            self.power = flush_denormal(self.power + (power - self.power) * coefficient);
+        }
+    }
+
+    fn rms(&self) -> f32 {
+        self.power.sqrt()
+    }
+}
+
+/// Highest oversampling factor of the saturation stage.
+const MAX_OVERSAMPLING: usize = 4;
+
+/// Q of each biquad in an 8th order Butterworth low-pass.
+const BUTTERWORTH_Q: [f32; 4] = [0.5098, 0.6013, 0.9000, 2.5629];
+
+/// Second order filter section, used to build steeper filters by chaining them.
+/// See https://www.w3.org/TR/audio-eq-cookbook/
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+/// Default Biquad values, passing the input straight through.
+impl Default for Biquad {
+    fn default() -> Biquad {
+        Biquad {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+}
+
+impl Biquad {
+    /// Turns the section into a low-pass at a cutoff given as a fraction of
+    /// the sample rate. Only the coefficients change, so the signal carries on.
+    fn set_lowpass(&mut self, cutoff: f32, q: f32) {
+        let w0 = 2.0 * PI as f32 * cutoff;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+
+        self.b0 = (1.0 - cos) / 2.0 / a0;
+        self.b1 = (1.0 - cos) / a0;
+        self.b2 = (1.0 - cos) / 2.0 / a0;
+        self.a1 = -2.0 * cos / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = flush_denormal(self.b1 * input - self.a1 * output + self.z2);
+        self.z2 = flush_denormal(self.b2 * input - self.a2 * output);
+
+        output
+    }
+}
+
+/// Runs the saturation stage at a multiple of the sample rate. The harmonics
+/// it adds above the original Nyquist frequency are filtered out before going
+/// back down to the original rate, instead of aliasing back into the audible range.
+struct Oversampler {
+    factor: usize,
+    previous: [f32; 2],
+    filters: [[Biquad; BUTTERWORTH_Q.len()]; 2],
+}
+
+/// Default Oversampler values, running at the plain sample rate.
+impl Default for Oversampler {
+    fn default() -> Oversampler {
+        let mut oversampler = Oversampler {
+            factor: 1,
+            previous: [0.0; 2],
+            filters: Default::default(),
+        };
+        oversampler.set_factor(1);
+
+        oversampler
+    }
+}
+
+impl Oversampler {
+    /// Switches to a new factor. The low-pass sits just below the original
+    /// Nyquist frequency, which is a smaller fraction of the faster rate.
+    fn set_factor(&mut self, factor: usize) {
+        self.factor = factor.clamp(1, MAX_OVERSAMPLING);
+        let cutoff = 0.45 / self.factor as f32;
+        for filters in self.filters.iter_mut() {
+            for (filter, &q) in filters.iter_mut().zip(BUTTERWORTH_Q.iter()) {
+                filter.set_lowpass(cutoff, q);
+            }
+        }
+    }
+
+    /// Clears the interpolation and filter state, keeping the factor.
+    fn reset(&mut self) {
+        self.previous = [0.0; 2];
+        for filter in self.filters.iter_mut().flat_map(|filters| filters.iter_mut()) {
+            filter.z1 = 0.0;
+            filter.z2 = 0.0;
+        }
+    }
+
+    /// Fills in the samples between the last input and this one by linear interpolation.
+    fn upsample(&mut self, input: f32, channel: usize) -> [f32; MAX_OVERSAMPLING] {
+        let mut samples = [0.0; MAX_OVERSAMPLING];
+        let previous = self.previous[channel];
+        for (step, sample) in samples.iter_mut().enumerate().take(self.factor) {
+            *sample = previous + (input - previous) * (step + 1) as f32 / self.factor as f32;
+        }
+        self.previous[channel] = input;
+
+        samples
+    }
+
+    /// Low-pass filters the processed samples and keeps the last one.
+    fn downsample(&mut self, samples: &[f32], channel: usize) -> f32 {
+        let mut output = 0.0;
+        for &sample in samples.iter().take(self.factor) {
+            output = sample;
+            for filter in self.filters[channel].iter_mut() {
+                output = filter.process(output);
+            }
+        }
+
+        output
+    }
+}
+
+/// Maps the oversampling slider onto 1, 2, or 4 times the sample rate.
+fn oversampling_factor(val: f32) -> usize {
+    if val < 0.33 {
+        1
+    } else if val < 0.66 {
+        2
+    } else {
+        4
+    }
+}
+
+/// Stores the arpeggiator settings, the keys being held, and where it is in the pattern.
+struct Arpeggiator {
+    enabled: f32,
+    rate: f32,
+    mode: f32,
+    octaves: f32,
+    keys: Vec<u8>,
+    velocity: u8,
+    step: usize,
+    clock: f64,
+    playing: Option<u8>,
+}
+
+/// Default Arpeggiator values, switched off.
+impl Default for Arpeggiator {
+    fn default() -> Arpeggiator {
+        Arpeggiator {
+            enabled: 0.0,
+            rate: 0.5,
+            mode: 0.0,
+            octaves: 0.0,
+            keys: Vec::new(),
+            velocity: 0,
+            step: 0,
+            clock: 0.0,
+            playing: None,
+        }
+    }
+}
+
+impl Arpeggiator {
+    /// The upper half of the arpeggiator slider switches it on.
+    fn is_on(&self) -> bool {
+        self.enabled >= 0.5
+    }
+
+    /// Maps the 0 to 1 rate slider logarithmically onto 0.5 to 20 notes per second.
+    fn rate_hz(&self) -> f32 {
+        0.5 * 40.0_f32.powf(self.rate)
+    }
+
+    /// Maps the octave slider onto playing the held keys across 1 to 4 octaves.
+    fn octave_count(&self) -> u8 {
+        1 + (self.octaves * 3.0).round() as u8
+    }
+
+    /// Name of the pattern picked by the mode slider.
+    fn mode_name(&self) -> String {
+        if self.mode < 0.25 {
+            "Up".to_string()
+        } else if self.mode < 0.5 {
+            "Down".to_string()
+        } else if self.mode < 0.75 {
+            "Up-down".to_string()
+        } else {
+            "Random".to_string()
+        }
+    }
+
+    /// The held keys from lowest to highest, repeated an octave up for each extra octave.
+    fn pattern(&self) -> Vec<u8> {
+        let mut keys = self.keys.clone();
+        keys.sort_unstable();
+
+        (0..self.octave_count())
+            .flat_map(|octave| keys.iter().map(move |&key| key as u16 + 12 * octave as u16))
+            .filter(|&note| note < 128)
+            .map(|note| note as u8)
+            .collect()
+    }
+
+    /// Picks the next note of the pattern in the order set by the mode. Up-down
+    /// doesn't repeat the top and bottom notes when it turns around. Random
+    /// mode draws from the synth's generator so seeded renders repeat.
+    fn next_note(&mut self, rng: &mut SmallRng) -> Option<u8> {
+        let pattern = self.pattern();
+        let length = pattern.len();
+        if length == 0 {
+            return None;
+        }
+
+        let index = if self.mode < 0.25 {
+            self.step % length
+        } else if self.mode < 0.5 {
+            length - 1 - self.step % length
+        } else if self.mode < 0.75 {
+            let period = (2 * length - 2).max(1);
+            let position = self.step % period;
+            if position < length { position } else { period - position }
+        } else {
+            rng.gen::<u32>() as usize % length
+        };
+        self.step += 1;
+
+        Some(pattern[index])
+    }
+}
+
+/// Stores values for the synth as a whole.
+pub struct Crust {
+    sample_rate: f64,
+    oscillators: Vec<Oscillator>,
+    notes: Vec<Voice>,
+    noise: f32,
+    envelope: Envelope,
+    filter: Filter,
+    lfo: Lfo,
+    master_vol: f32,
+    pitch_bend: f32,
+    bend_range: f32,
+    sustain_pedal: bool,
+    sustained_notes: Vec<u8>,
+    max_voices: usize,
+    sub_level: f32,
+    sub_wave: f32,
+    fm_amount: f32,
+    fm_routing: f32,
+    mix_mode: f32,
+    unison_count: usize,
+    unison_spread: f32,
+    distortion: f32,
+    distortion_volume: f32,
+    saturation_type: f32,
+    drive: f32,
+    glide_time: f32,
+    glide_speed: f64,
+    current_freq: f64,
+    target_freq: f64,
+    midi_channel: f32,
+    preset: i32,
+    filter_envelope: Envelope,
+    filter_env_amount: f32,
+    tuning: Tuning,
+    noise_generator: NoiseGenerator,
+    delay: Delay,
+    chorus: Chorus,
+    bitcrusher: Bitcrusher,
+    reverb: Reverb,
+    voice_mode: f32,
+    note_stack: Vec<u8>,
+    velocity_to_cutoff: f32,
+    key_track: f32,
+    arpeggiator: Arpeggiator,
+    host: Option<HostCallback>,
+    tempo: f64,
+    soft_clipping: f32,
+    normalization: f32,
+    normalization_level: SmoothedParam,
+    aftertouch: f32,
+    aftertouch_target: f32,
+    mod_wheel: f32,
+    mod_wheel_target: f32,
+    cc_map: HashMap<u8, i32>,
+    hard_sync: f32,
+    wavetable: Wavetable,
+    effects_mix: f32,
+    glide_legato: f32,
+    osc_balance: SmoothedParam,
+    balance_mode: f32,
+    width: f32,
+    oversampling: f32,
+    oversampler: Oversampler,
+    velocity_to_envelope: f32,
+    latch: f32,
+    latched_notes: Vec<u8>,
+    noise_only: f32,
+    auto_gain: f32,
+    auto_gain_level: AutoGain,
+    relative_noise: f32,
+    split: f32,
+    split_point: u8,
+    drift: f32,
+    drift_seed: u32,
+    meter: Meter,
+    noise_enabled: f32,
+    limiter: Limiter,
+    dc_blocker: DcBlocker,
+    phase_reset: f32,
+    sample_hold: SampleHold,
+    shared: Arc<SharedParameters>,
+    rng: SmallRng,
+    block: Vec<[f32; 2]>,
+}
+
+/// Default synth values.
+impl Default for Crust {
+    fn default() -> Crust {
+        Crust {
+            sample_rate: 44100.0,
+            oscillators: vec![Default::default(), Default::default()],
+            notes: Vec::new(),
+            noise: 0.0,
+            envelope: Envelope::default(),
+            filter: Filter::default(),
+            lfo: Lfo::default(),
+            master_vol: 1.0,
+            pitch_bend: 0.0,
+            bend_range: 2.0,
+            sustain_pedal: false,
+            sustained_notes: Vec::new(),
+            max_voices: 16,
+            sub_level: 0.0,
+            sub_wave: 0.0,
+            fm_amount: 0.0,
+            fm_routing: 0.0,
+            mix_mode: 0.0,
+            unison_count: 1,
+            unison_spread: 0.0,
+            distortion: 0.0,
+            distortion_volume: 1.0,
+            saturation_type: 0.0,
+            drive: 0.0,
+            glide_time: 0.0,
+            glide_speed: 0.0,
+            current_freq: 0.0,
+            target_freq: 0.0,
+            midi_channel: 0.0,
+            preset: 0,
+            filter_envelope: Envelope::default(),
+            filter_env_amount: 0.0,
+            tuning: Tuning::default(),
+            noise_generator: NoiseGenerator::default(),
+            delay: Delay::default(),
+            chorus: Chorus::default(),
+            bitcrusher: Bitcrusher::default(),
+            reverb: Reverb::default(),
+            voice_mode: 0.0,
+            note_stack: Vec::new(),
+            velocity_to_cutoff: 0.0,
+            key_track: 0.0,
+            arpeggiator: Arpeggiator::default(),
+            host: None,
+            tempo: DEFAULT_TEMPO,
+            soft_clipping: 0.0,
+            normalization: 0.0,
+            normalization_level: SmoothedParam::new(1.0),
+            aftertouch: 0.0,
+            aftertouch_target: 0.0,
+            mod_wheel: 0.0,
+            mod_wheel_target: 0.0,
+            cc_map: HashMap::new(),
+            hard_sync: 0.0,
+            wavetable: Wavetable::default(),
+            effects_mix: 1.0,
+            glide_legato: 0.0,
+            osc_balance: SmoothedParam::new(0.5),
+            balance_mode: 0.0,
+            width: 0.5,
+            oversampling: 0.0,
+            oversampler: Oversampler::default(),
+            velocity_to_envelope: 1.0,
+            latch: 0.0,
+            latched_notes: Vec::new(),
+            noise_only: 0.0,
+            auto_gain: 0.0,
+            auto_gain_level: AutoGain::default(),
+            relative_noise: 0.0,
+            split: 0.0,
+            split_point: 60,
+            drift: 0.0,
+            drift_seed: 0,
+            meter: Meter::default(),
+            noise_enabled: 1.0,
+            limiter: Limiter::default(),
+            dc_blocker: DcBlocker::default(),
+            phase_reset: 0.0,
+            sample_hold: SampleHold::default(),
+            shared: Arc::new(SharedParameters::default()),
+            rng: noise_rng(random::<u64>()),
+            block: Vec::with_capacity(DEFAULT_BLOCK_SIZE),
+        }
+    }
+}
+
+/// Creates a sine wave based on midi note, oscillator volume, time, and detune value.
+#[cfg(test)]
+fn create_sine_wave(midi_note: u8, volume: f32, time: f64, detune: f32) -> f32 {
+    volume * (time as f32 * midi_note_num_to_freq(midi_note, detune, &Tuning::default()) as f32 * 2.0 * PI as f32).sin()
+}
+
+/// Creates a sawtooth wave based on midi note, oscillator volume, time, and detune value.
+#[cfg(test)]
+fn create_sawtooth_wave(midi_note: u8, volume: f32, time: f64, detune: f32) -> f32 {
+    volume * (time *  midi_note_num_to_freq(midi_note, detune, &Tuning::default()) - ((time *  midi_note_num_to_freq(midi_note, detune, &Tuning::default())).floor()) - 0.5) as f32
+}
+
+/// Creates a square wave based on midi note, oscillator volume, time, and detune value.
+#[cfg(test)]
+fn create_square_wave(midi_note: u8, volume: f32, time: f64, detune: f32) -> f32 {
+    if (time * midi_note_num_to_freq(midi_note, detune, &Tuning::default()) * 2.0 * PI).sin() as f32 >= 0.0 {
+        volume * SQUARE_BALANCE
+    } else {
+        volume * -SQUARE_BALANCE
+    }
+}
+
+/// Creates a triangle wave based on midi note, oscillator volume, time, and detune value.
+#[cfg(test)]
+fn create_triangle_wave(midi_note: u8, volume: f32, time: f64, detune: f32) -> f32 {
+    volume * ((((time *  midi_note_num_to_freq(midi_note, detune, &Tuning::default())) - ((time *  midi_note_num_to_freq(midi_note, detune, &Tuning::default())).floor()) - 0.5).abs() - 0.25) * 4.0) as f32
+}
+
+/// Creates a sine wave based on oscillator phase and volume.
+fn create_sine_wave_from_phase(phase: f64, volume: f32) -> f32 {
+    volume * (phase * 2.0 * PI).sin() as f32
+}
+
+/// Creates a band limited sawtooth wave based on phase, phase increment per sample,
+/// and oscillator volume. The naive ramp aliases badly on high notes, so a PolyBLEP
+/// correction smooths out the step where the ramp wraps around.
+fn create_sawtooth_wave_blep(phase: f64, dt: f64, volume: f32) -> f32 {
+    volume * (phase - 0.5 - 0.5 * poly_blep(phase, dt)) as f32
+}
+
+/// Creates a band limited pulse wave based on phase, phase increment per sample,
+/// oscillator volume, pulse width, and peak level. Both the rising and falling edges
+/// get a PolyBLEP correction. A pulse width of 0.5 gives the same symmetric shape as
+/// the plain square wave, other widths can be used for pulse width modulation.
+fn create_square_wave_blep(phase: f64, dt: f64, volume: f32, pulse_width: f32, level: f32) -> f32 {
+    let pulse_width = pulse_width.max(0.05).min(0.95) as f64;
+    let mut value = if phase < pulse_width { 1.0 } else { -1.0 };
+
+    value += poly_blep(phase, dt);
+    value -= poly_blep((phase + 1.0 - pulse_width) % 1.0, dt);
+
+    volume * level * value as f32
+}
+
+/// Creates a triangle wave based on oscillator phase and volume.
+fn create_triangle_wave_from_phase(phase: f64, volume: f32) -> f32 {
+    volume * (((phase - 0.5).abs() - 0.25) * 4.0) as f32
+}
+
+/// Creates the sine, saw, square, or triangle wave picked by the oscillator's
+/// waveform slider. In morph mode the morph position crossfades between
+/// neighbouring waveforms instead.
+fn create_wave(oscillator: &Oscillator, wavetable: &Wavetable, phase: f64, dt: f64, volume: f32) -> f32 {
+    if oscillator.morph >= 0.5 {
+        let (index, amount) = morph_blend(oscillator.morph_position);
+        let from = create_waveform(index, oscillator, wavetable, phase, dt, volume);
+        let to = create_waveform(index + 1, oscillator, wavetable, phase, dt, volume);
+        return from * (1.0 - amount) + to * amount;
+    }
+
+    create_waveform(oscillator.wave_index, oscillator, wavetable, phase, dt, volume)
+}
+
+/// Creates one of the waveforms by its index in WAVEFORM_NAMES.
+fn create_waveform(wave_index: usize, oscillator: &Oscillator, wavetable: &Wavetable, phase: f64, dt: f64, volume: f32) -> f32 {
+    match wave_index {
+        0 => create_sine_wave_from_phase(phase, volume),
+        1 => create_sawtooth_wave_blep(phase, dt, volume),
+        2 => create_square_wave_blep(phase, dt, volume, oscillator.pulse_width, oscillator.square_level()),
+        3 => create_triangle_wave_from_phase(phase, volume),
+        _ => volume * wavetable.sample(oscillator.table_position, phase),
+    }
+}
+
+/// Position of a unison copy between -1 and 1. The copies are spread evenly
+/// and symmetrically around the note, a single copy sits in the middle.
+fn unison_offset(copy: usize, count: usize) -> f32 {
+    if count > 1 {
+        copy as f32 / (count - 1) as f32 * 2.0 - 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Detune in cents of a unison copy. The spread slider sets how far the outer
+/// copies go, up to UNISON_SPREAD_CENTS either side. The cents are added to the
+/// oscillator detune, so both go through the same frequency calculation.
+fn unison_detune(copy: usize, count: usize, spread: f32) -> f32 {
+    unison_offset(copy, count) * spread * UNISON_SPREAD_CENTS
+}
+
+/// Advances an oscillator phase by one phase increment, wrapping it to stay between 0 and 1.
+fn advance_phase(phase: f64, dt: f64) -> f64 {
+    (phase + dt) % 1.0
+}
+
+/// Polynomial band limited step used to remove aliasing from the discontinuities
+/// of the sawtooth and square waves. The phase is between 0 and 1 and dt is
+/// the phase increment per sample.
+/// See http://www.martin-finke.de/blog/articles/audio-plugins-018-polyblep-oscillator/
+fn poly_blep(phase: f64, dt: f64) -> f64 {
+    if phase < dt {
+        let t = phase / dt;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - dt {
+        let t = (phase - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Midi note numbers are converted to a frequency value using the tuning, then adjusted
+/// for detuning, if any. Detune is in cents, so it is the same musical interval for every note.
+fn midi_note_num_to_freq(midi_note_number: u8, detune: f32, tuning: &Tuning) -> f64 {
+    let cents = detune + tuning.cents[midi_note_number as usize % 12];
+
+    ((midi_note_number as f64 - 69.0) / 12.0).exp2() * tuning.reference as f64 * (cents as f64 / 1200.0).exp2()
+}
+
+/// Offsets the carrier phase by the modulator output for phase modulation.
+/// The modulation index is the peak phase deviation in radians for a full
+/// scale modulator, so an index of 0 leaves the carrier untouched.
+fn fm_phase(phase: f64, modulator: f32, index: f32) -> f64 {
+    (phase + (index * modulator) as f64 / (2.0 * PI)).rem_euclid(1.0)
+}
+
+/// Ring modulation multiplies the two oscillators, giving metallic, bell like tones.
+fn ring_modulate(wave1: f32, wave2: f32) -> f32 {
+    wave1 * wave2
+}
+
+/// Amplitude modulation uses the modulator to swing the carrier's gain around unity.
+fn amplitude_modulate(carrier: f32, modulator: f32) -> f32 {
+    carrier * (1.0 + modulator)
+}
+
+/// Frequency of the sub oscillator, which sits one octave below the played note.
+fn sub_freq(midi_note_number: u8, tuning: &Tuning) -> f64 {
+    if midi_note_number >= 12 {
+        midi_note_num_to_freq(midi_note_number - 12, 0.0, tuning)
+    } else {
+        midi_note_num_to_freq(midi_note_number, 0.0, tuning) / 2.0
+    }
+}
+
+/// Shifts a frequency by the given number of semitones, e.g. the current pitch bend.
+fn bend_freq(freq: f64, semitones: f32) -> f64 {
+    freq * (semitones as f64 / 12.0).exp2()
+}
+
+/// Converts the 14 bit value of a pitch bend message into a bend between -1 and 1,
+/// where the center value of 8192 is no bend at all.
+fn pitch_bend_to_offset(lsb: u8, msb: u8) -> f32 {
+    let value = ((msb as i32 & 0x7f) << 7) | (lsb as i32 & 0x7f);
+
+    if value >= 8192 {
+        (value - 8192) as f32 / 8191.0
+    } else {
+        (value - 8192) as f32 / 8192.0
+    }
+}
+
+/// Equal power left and right gains for a pan position between 0 (left) and 1 (right).
+fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = pan * PI as f32 / 2.0;
+
+    [angle.cos(), angle.sin()]
+}
+
+/// Converts a midi velocity into a gain between 0 and 1 which scales the
+/// peak of the voice's envelope.
+fn velocity_to_gain(velocity: u8) -> f32 {
+    velocity as f32 / 127.0
+}
+
+/// Peak level of the envelope for a velocity. At an amount of 0 every note
+/// reaches full level, at 1 the peak follows the velocity all the way.
+fn velocity_to_peak(velocity: u8, amount: f32) -> f32 {
+    1.0 - amount + amount * velocity_to_gain(velocity)
+}
+
+/// Shapes the progress through an envelope segment, from 0 at the start of the
+/// segment to 1 at the end. A curve of 0 is a straight line, a curve of 1 is an
+/// exponential curve normalized to still reach 1 at the end of the segment.
+fn envelope_curve(progress: f32, curve: f32) -> f32 {
+    let k: f32 = 5.0;
+    let exponential = (1.0 - (-k * progress).exp()) / (1.0 - (-k).exp());
+
+    (1.0 - curve) * progress + curve * exponential
+}
+
+/// How far through a segment of the given length the elapsed time is, from 0
+/// to 1. A segment of length 0 is already over, rather than dividing by zero.
+fn segment_progress(elapsed: f32, length: f32) -> f32 {
+    if length > 0.0 {
+        (elapsed / length).min(1.0)
+    } else {
+        1.0
+    }
+}
+
+/// Determines which phase of the ADS portion of the Envelope filter the voice is in
+/// and returns the amplitude at that point in time.
+/// The attack rises to master_vol and sustain is a fraction of that peak.
+/// Each segment is shaped by its own curve.
+/// This method is called while a key is held.
+fn get_amplitude(envelope: &Envelope, voice: &Voice, master_vol: f32) -> f32 {
+    let sustain_level = envelope.sustain * master_vol;
+
+    if voice.duration as f32 <= envelope.attack {
+        //attack phase
+       envelope_curve(segment_progress(voice.duration as f32, envelope.attack), envelope.attack_curve) * master_vol
+   } else if voice.duration as f32 > envelope.attack && voice.duration as f32 <= (envelope.attack + envelope.decay) {
+       // decay phase
+       let progress = segment_progress(voice.duration as f32 - envelope.attack, envelope.decay);
+       envelope_curve(progress, envelope.decay_curve) * (sustain_level - master_vol) + master_vol
+   } else {
+       // sustain phase
+       sustain_level
+   }
+}
+
+/// Determines the amplitude of the voice during the Release phase of the Envelope filter.
+/// The release ramps down from the level the envelope was at when the key was lifted,
+/// so releasing in the middle of the attack or decay doesn't jump.
+/// This method is called once a key is lifted.
+fn generate_release(envelope: &Envelope, voice: &Voice, start_level: f32) -> f32 {
+    let progress = segment_progress(voice.end_time as f32, voice.release_time(envelope));
+    envelope_curve(progress, envelope.release_curve) * (0.0 - start_level) + start_level
+}
+
+/// Basic distortion formula based on input signal and desired distortion level.
+/// The input is expected to be between -1 and 1 and the distortion level between
+/// 0 (clean) and 1. Higher levels can push the output far outside of -1 to 1.
+/// Silence stays silent, since the formula divides by the size of the input.
+/// Formula is based on
+/// https://ccrma.stanford.edu/~orchi/Documents/DAFx.pdf
+fn distortion(input: f32, dist: f32, dist_volume: f32) -> f32 {
+    // if dist_volume == 0.0 {
+    //     input
+    // } else {
+    //     dist_volume * ((input * (1.0 - (dist * (input).exp2() / input.abs()).exp())) / input.abs())
+    // }
+
+    if input == 0.0 {
+        return 0.0;
+    }
+
+    let gain = 5.0;
+    let q = input / input.abs();
+    let y = q * (1.0 - (gain * (q * input)).exp());
+    let z = dist * y + (1.0 - dist) * input;
+
+    dist_volume * z
+}
+
+/// Basic overdrive formula which is determined by the input signal.
+/// The overdrive has 3 phases which spits the input signal in thirds
+/// and generates a different output for each phase. Negative input is
+/// mirrored so the curve is symmetric, anything louder than 1 is held at 1,
+/// and NaN input gives silence. The phases meet without jumps.
+/// Formula is based on
+/// https://ccrma.stanford.edu/~orchi/Documents/DAFx.pdf
+fn overdrive(input: f32) -> f32 {
+    if input.is_nan() {
+        0.0
+    } else if input < 0.0 {
+        -overdrive(-input)
+    } else if input < 1.0 / 3.0 {
+        2.0 * input
+    } else if input < 2.0 / 3.0 {
+        (3.0 - (2.0 - (3.0 * input)).powi(2)) / 3.0
+    } else {
+        1.0
+    }
+}
+
+/// Smoothly squashes the input into -1 to 1 so loud chords bend instead of
+/// hitting the host's hard clipping. Quiet signals pass through almost unchanged.
+fn soft_clip(input: f32) -> f32 {
+    input.tanh()
+}
+
+/// Names of the places an expression controller like aftertouch can be sent.
+const EXPRESSION_TARGETS: [&str; 3] = ["Vibrato", "Cutoff", "Volume"];
+
+/// Maps a destination slider onto one of the expression targets, each of
+/// which gets an equal third of the slider.
+fn expression_target(val: f32) -> usize {
+    ((val * 3.0) as usize).min(EXPRESSION_TARGETS.len() - 1)
+}
+
+/// Handles incomming midi message data and determines whether to start or
+/// stop a particular note.
+/// See https://www.midi.org/specifications-old/item/table-1-summary-of-midi-message
+impl Crust {
+    fn process_midi_data(&mut self, midi_data: [u8; 3]) {
+        let status = midi_data[0] & 0xF0;
+        let channel = midi_data[0] & 0x0F;
+        if !self.listens_to(channel) {
+            return;
+        }
+
+        match status {
+            128 => self.note_off(midi_data[1]),
+            144 if midi_data[2] == 0 => self.note_off(midi_data[1]),
+            144 => self.note_on(midi_data[1], midi_data[2]),
+            176 => self.control_change(midi_data[1], midi_data[2]),
+            192 => self.program_change(midi_data[1]),
+            208 => self.aftertouch = midi_data[1] as f32 / 127.0,
+            224 => self.pitch_bend(midi_data[1], midi_data[2]),
+            _ => (),
+        }
+    }
+
+    /// Switches factory preset from a program change message. Programs past
+    /// the last preset load the last one.
+    fn program_change(&mut self, program: u8) {
+        let preset = (program as usize).min(FACTORY_PRESETS.len() - 1);
+        self.change_preset(preset as i32);
+    }
+
+    /// Maps the channel slider onto Omni (0) or MIDI channels 1 to 16.
+    fn channel_setting(&self) -> u8 {
+        (self.midi_channel * 16.0).round() as u8
+    }
+
+    /// Whether messages on the zero based MIDI channel should be played,
+    /// which is every channel in Omni mode.
+    fn listens_to(&self, channel: u8) -> bool {
+        let setting = self.channel_setting();
+        setting == 0 || setting == channel + 1
+    }
+
+    /// Plays the note, or hands the key to the arpeggiator when it is on.
+    /// The first key the arpeggiator gets starts its pattern straight away.
+    /// In latch mode playing a latched note again lets go of it instead.
+    fn note_on(&mut self, note: u8, velocity: u8) {
+        if self.is_latched() {
+            if let Some(position) = self.latched_notes.iter().position(|&x| x == note) {
+                self.latched_notes.remove(position);
+                self.lift_key(note);
+                return;
+            }
+            self.latched_notes.push(note);
+        }
+
+        if self.arpeggiator.is_on() {
+            let first_key = self.arpeggiator.keys.is_empty();
+            if !self.arpeggiator.keys.contains(&note) {
+                self.arpeggiator.keys.push(note);
+            }
+            self.arpeggiator.velocity = velocity;
+
+            if first_key {
+                self.arpeggiator.step = 0;
+                self.arpeggiator.clock = 0.0;
+                self.arpeggiate();
+            }
+            return;
+        }
+
+        self.play_note(note, velocity);
+    }
+
+    /// Releases the note the arpeggiator last played and plays the next one.
+    fn arpeggiate(&mut self) {
+        if let Some(note) = self.arpeggiator.playing.take() {
+            self.release_note(note);
+        }
+
+        if let Some(note) = self.arpeggiator.next_note(&mut self.rng) {
+            let velocity = self.arpeggiator.velocity;
+            self.play_note(note, velocity);
+            self.arpeggiator.playing = Some(note);
+        }
+    }
+
+    /// Moves the arpeggiator clock forward by one sample period and steps to
+    /// the next note when it is due. Switching the arpeggiator off releases its note.
+    fn advance_arpeggiator(&mut self, sample: f64) {
+        if !self.arpeggiator.is_on() {
+            if let Some(note) = self.arpeggiator.playing.take() {
+                self.release_note(note);
+            }
+            self.arpeggiator.keys.clear();
+            return;
+        }
+        if self.arpeggiator.keys.is_empty() {
+            return;
+        }
+
+        let step_time = 1.0 / self.arpeggiator.rate_hz() as f64;
+        self.arpeggiator.clock += sample;
+        if self.arpeggiator.clock >= step_time {
+            self.arpeggiator.clock -= step_time;
+            self.arpeggiate();
+        }
+    }
+
+    /// Adds a new voice for the note to the vector of active notes.
+    /// The voice starts its own duration timer for the envelope filter and
+    /// remembers the velocity it was played with.
+    /// In legato mode a note played while another is held just moves the held
+    /// voice to the new pitch without restarting its envelope.
+    fn play_note(&mut self, note: u8, velocity: u8) {
+        let overlapping = self.notes.iter().any(|voice| voice.note_on);
+        self.sustained_notes.retain(|&x| x != note);
+        if self.lfo.retriggers() {
+            self.lfo.phase = 0.0;
+        }
+
+        // With phase reset on every copy starts from the oscillator's start
+        // phase, including a legato voice that is already playing.
+        let reset = self.phase_reset >= 0.5;
+        let start_phases = self.offset_phases([[0.0; 2]; MAX_UNISON]);
+
+        if self.is_legato() {
+            self.note_stack.retain(|&x| x != note);
+            self.note_stack.push(note);
+
+            if let Some(voice) = self.notes.iter_mut().rev().find(|voice| voice.note_on) {
+                voice.midi_note = note;
+                if reset {
+                    voice.phases = start_phases;
+                    voice.sub_phase = 0.0;
+                }
+                self.start_glide(midi_note_num_to_freq(note, 0.0, &self.tuning), true);
+                return;
+            }
+        }
+
+        let max_voices = if self.is_legato() { 1 } else { self.max_voices };
+        while self.active_voices() >= max_voices {
+            self.steal_oldest_voice();
+        }
+        let mut voice = Voice::new(note, velocity);
+        voice.drift = Drift::new(self.drift_seed);
+        self.drift_seed = self.drift_seed.wrapping_add(1);
+        voice.phases = if reset {
+            start_phases
+        } else {
+            let phases = self.random_phases();
+            self.offset_phases(phases)
+        };
+        self.notes.push(voice);
+        self.start_glide(midi_note_num_to_freq(note, 0.0, &self.tuning), overlapping);
+    }
+
+    /// The first unison copy starts at phase 0, the others start at random
+    /// phases so the stack doesn't sound thin and comb filtered.
+    fn random_phases(&mut self) -> [[f64; 2]; MAX_UNISON] {
+        let mut phases = [[0.0; 2]; MAX_UNISON];
+        for copy in phases.iter_mut().skip(1) {
+            *copy = [self.rng.gen::<f64>(), self.rng.gen::<f64>()];
+        }
+
+        phases
+    }
+
+    /// Shifts the phases of every unison copy by each oscillator's phase offset.
+    fn offset_phases(&self, mut phases: [[f64; 2]; MAX_UNISON]) -> [[f64; 2]; MAX_UNISON] {
+        for copy in phases.iter_mut() {
+            for (phase, oscillator) in copy.iter_mut().zip(self.oscillators.iter()) {
+                *phase = (*phase + oscillator.phase_offset as f64) % 1.0;
+            }
+        }
+
+        phases
+    }
+
+    /// The upper half of the voice mode slider switches to monophonic legato.
+    fn is_legato(&self) -> bool {
+        self.voice_mode >= 0.5
+    }
+
+    /// Gain that keeps chords from getting louder with every note. The middle of
+    /// the normalization slider divides by the square root of the number of
+    /// held voices, the top divides by the number itself, and the bottom
+    /// leaves the voices adding up. Releasing voices aren't counted, so a
+    /// released chord doesn't keep the next note quiet.
+    fn normalization_gain(&self) -> f32 {
+        let voices = self.notes.iter().filter(|voice| voice.note_on).count().max(1) as f32;
+
+        if self.normalization < 0.33 {
+            1.0
+        } else if self.normalization < 0.66 {
+            1.0 / voices.sqrt()
+        } else {
+            1.0 / voices
+        }
+    }
+
+    /// How far aftertouch and the mod wheel push each target: extra vibrato depth on
+    /// the Lfo, how far the cutoff slider is opened up to half its range, and
+    /// how much louder the output gets, up to twice as loud.
+    fn expression(&self) -> [f32; 3] {
+        let mut amounts = [0.0; 3];
+        amounts[expression_target(self.aftertouch_target)] += self.aftertouch;
+        amounts[expression_target(self.mod_wheel_target)] += self.mod_wheel;
+
+        amounts
+    }
+
+    /// Reads the tempo from the host for the tempo synced Lfo and delay.
+    /// The last known tempo is kept when the host doesn't report one. Without
+    /// a host, as when the synth is built with default() in tests, the default tempo is used.
+    fn update_tempo(&mut self) {
+        match self.host {
+            Some(ref host) => {
+                if let Some(info) = host.get_time_info(TEMPO_VALID) {
+                    if info.flags & TEMPO_VALID != 0 && info.tempo > 0.0 {
+                        self.tempo = info.tempo;
+                    }
+                }
+            }
+            None => self.tempo = DEFAULT_TEMPO,
+        }
+    }
+
+    /// Delay in samples that lookahead stages add to the output, which the host
+    /// compensates for. Only the limiter looks ahead, and its delay stays in
+    /// while it is off so the host never has to be told about a change.
+    fn latency(&self) -> i32 {
+        self.limiter.latency() as i32
+    }
+
+    /// How many samples the output keeps ringing after the last note is
+    /// released, which is the release time followed by the delay's echoes
+    /// and the reverb.
+    fn tail_samples(&self) -> f64 {
+        self.envelope.release as f64 * self.sample_rate
+            + self.delay.tail_samples(self.sample_rate, self.tempo)
+            + self.reverb.tail_samples()
+    }
+
+    /// The synth is monophonic when polyphony is limited to a single voice
+    /// or when it is in legato mode.
+    fn is_mono(&self) -> bool {
+        self.max_voices == 1 || self.is_legato()
+    }
+
+    /// In monophonic mode the pitch slides from the previous note to the new
+    /// one over the glide time. Otherwise, or with a glide time of 0, the new
+    /// pitch is used straight away. With glide legato switched on, only notes
+    /// played while another key is still held glide.
+    fn start_glide(&mut self, freq: f64, overlapping: bool) {
+        self.target_freq = freq;
+        let legato_only = self.glide_legato >= 0.5;
+
+        if self.is_mono() && self.glide_time > 0.0 && self.current_freq > 0.0 && (overlapping || !legato_only) {
+            self.glide_speed = (12.0 * (self.target_freq / self.current_freq).log2()).abs() / self.glide_time as f64;
+        } else {
+            self.current_freq = freq;
+        }
+    }
+
+    /// Moves the current pitch one sample period closer to the target pitch,
+    /// at a constant number of semitones per second.
+    fn advance_glide(&mut self, sample: f64) {
+        if self.current_freq == self.target_freq {
+            return;
+        }
+
+        let distance = 12.0 * (self.target_freq / self.current_freq).log2();
+        let step = self.glide_speed * sample;
+
+        if distance.abs() <= step {
+            self.current_freq = self.target_freq;
+        } else {
+            self.current_freq *= (step.copysign(distance) / 12.0).exp2();
+        }
+    }
+
+    /// Offset in semitones between the gliding pitch and the note being played.
+    fn glide_offset(&self) -> f32 {
+        if self.current_freq == self.target_freq {
+            0.0
+        } else {
+            (12.0 * (self.current_freq / self.target_freq).log2()) as f32
+        }
+    }
+
+    /// Number of voices that count towards the polyphony limit.
+    fn active_voices(&self) -> usize {
+        self.notes.iter().filter(|voice| !voice.stolen).count()
+    }
+
+    /// Makes room for a new note by sending the oldest voice into a quick release.
+    /// Voices are kept in the order they were played, so the oldest comes first.
+    fn steal_oldest_voice(&mut self) {
+        if let Some(voice) = self.notes.iter_mut().find(|voice| !voice.stolen) {
+            voice.stolen = true;
+            voice.note_on = false;
+            voice.end_time = 0.0;
+        }
+    }
+
+    /// Lifts the key, except in latch mode where notes are held until they
+    /// are played again.
+    fn note_off(&mut self, note: u8) {
+        if !self.is_latched() {
+            self.lift_key(note);
+        }
+    }
+
+    /// The upper half of the latch slider holds notes after their keys are lifted.
+    fn is_latched(&self) -> bool {
+        self.latch >= 0.5
+    }
+
+    /// Switches latch mode, letting go of every latched note when it is turned off.
+    fn set_latch(&mut self, latch: f32) {
+        self.latch = latch;
+        if !self.is_latched() {
+            for note in std::mem::take(&mut self.latched_notes) {
+                self.lift_key(note);
+            }
+        }
+    }
+
+    /// Releases the note, unless the sustain pedal is down in which case the
+    /// note keeps sounding until the pedal is lifted.
+    fn lift_key(&mut self, note: u8) {
+        if self.arpeggiator.is_on() {
+            self.arpeggiator.keys.retain(|&x| x != note);
+            if self.arpeggiator.keys.is_empty() {
+                if let Some(playing) = self.arpeggiator.playing.take() {
+                    self.release_note(playing);
+                }
+            }
+            return;
+        }
+
+        if self.sustain_pedal {
+            if !self.sustained_notes.contains(&note) {
+                self.sustained_notes.push(note);
+            }
+        } else {
+            self.release_note(note);
+        }
+    }
+
+    /// Starts the release phase of every held voice playing the note.
+    /// The voice is removed from the active note vector once its release has finished.
+    /// In legato mode the voice goes back to the last key that is still held instead.
+    fn release_note(&mut self, note: u8) {
+        if self.is_legato() {
+            self.note_stack.retain(|&x| x != note);
+
+            if let Some(&previous) = self.note_stack.last() {
+                if let Some(voice) = self.notes.iter_mut().find(|voice| voice.midi_note == note && voice.note_on) {
+                    voice.midi_note = previous;
+                    self.start_glide(midi_note_num_to_freq(previous, 0.0, &self.tuning), true);
+                }
+                return;
+            }
+        }
+
+        for voice in self.notes.iter_mut().filter(|voice| voice.midi_note == note && voice.note_on) {
+            voice.note_on = false;
+            voice.end_time = 0.0;
+        }
+    }
+
+    /// Handles control change messages.
+    /// See https://www.midi.org/specifications-old/item/table-3-control-change-messages-data-bytes-2
+    /// Controllers assigned to a parameter with map_controller move that
+    /// parameter instead of their fixed function.
+    fn control_change(&mut self, controller: u8, value: u8) {
+        if let Some(&index) = self.cc_map.get(&controller) {
+            self.set_parameter(index, value as f32 / 127.0);
+            return;
+        }
+
+        match controller {
+            1 => self.mod_wheel = value as f32 / 127.0,
+            64 => self.sustain(value >= 64),
+            120 => self.all_sound_off(),
+            123 => self.all_notes_off(),
+            _ => (),
+        }
+    }
+
+    /// Assigns a controller number to a parameter index. Passing None removes the mapping.
+    fn map_controller(&mut self, controller: u8, index: Option<i32>) {
+        match index {
+            Some(index) => self.cc_map.insert(controller, index),
+            None => self.cc_map.remove(&controller),
+        };
+    }
+
+    /// Releases every held voice, including the ones held by the sustain pedal.
+    fn all_notes_off(&mut self) {
+        self.sustained_notes.clear();
+        self.note_stack.clear();
+        self.latched_notes.clear();
+        for voice in self.notes.iter_mut().filter(|voice| voice.note_on) {
+            voice.note_on = false;
+            voice.end_time = 0.0;
+        }
+    }
+
+    /// Silences every voice straight away, skipping their release.
+    fn all_sound_off(&mut self) {
+        self.sustained_notes.clear();
+        self.note_stack.clear();
+        self.latched_notes.clear();
+        self.notes.clear();
+    }
+
+    /// Stops everything at once for hosts and tests. On top of silencing the
+    /// voices this empties the filter and every effect, so no tail rings on.
+    pub fn panic(&mut self) {
+        self.all_sound_off();
+        self.filter.ic1eq = [0.0; 2];
+        self.filter.ic2eq = [0.0; 2];
+        self.oversampler.reset();
+        self.auto_gain_level = AutoGain::default();
+        self.chorus.resize(self.sample_rate);
+        self.delay.resize(self.sample_rate);
+        self.reverb.resize(self.sample_rate);
+        self.limiter.reset();
+        self.dc_blocker = DcBlocker { enabled: self.dc_blocker.enabled, ..Default::default() };
+        self.bitcrusher.held = [0.0; 2];
+        self.bitcrusher.counter = 0;
+    }
+
+    /// Seeds the noise source, the sample and hold, and the generator behind
+    /// the unison start phases and the random arpeggio, so renders with the
+    /// same seed come out the same.
+    pub fn set_noise_seed(&mut self, seed: u64) {
+        self.noise_generator.seed(seed);
+        self.sample_hold.noise.seed(seed.wrapping_add(1));
+        self.rng = noise_rng(seed.wrapping_add(2));
+    }
+
+    /// Peak level of the output, as of the last processed buffer.
+    pub fn output_peak(&self) -> f32 {
+        self.meter.peak
+    }
+
+    /// RMS level of the output, as of the last processed buffer.
+    pub fn output_rms(&self) -> f32 {
+        self.meter.rms()
+    }
+
+    /// Handle an editor uses to read and set parameters from its own thread.
+    pub fn shared_parameters(&self) -> Arc<SharedParameters> {
+        Arc::clone(&self.shared)
+    }
+
+    /// Applies the values the editor has set since the last block.
+    fn apply_editor_changes(&mut self) {
+        for index in 0..PARAMETERS.len() {
+            if let Some(value) = self.shared.take(index) {
+                self.set_parameter(index as i32, value);
+            }
+        }
+    }
+
+    /// Holds every note whose key is lifted while the pedal is down.
+    /// Lifting the pedal releases all of the held notes together.
+    fn sustain(&mut self, pedal_down: bool) {
+        self.sustain_pedal = pedal_down;
+
+        if !pedal_down {
+            let sustained_notes: Vec<u8> = self.sustained_notes.drain(..).collect();
+            for note in sustained_notes {
+                self.release_note(note);
+            }
+        }
+    }
+
+    /// Stores the pitch bend from -1 to 1. Every sounding voice picks up the
+    /// new pitch on the next sample.
+    fn pitch_bend(&mut self, lsb: u8, msb: u8) {
+        self.pitch_bend = pitch_bend_to_offset(lsb, msb);
+    }
+
+    /// The pitch bend in semitones. The range is applied here rather than when
+    /// the bend arrives, so changing it moves a bend that is already held.
+    fn bend_semitones(&self) -> f32 {
+        self.pitch_bend * self.bend_range
+    }
+
+    /// FM is switched on by the upper half of the routing slider. The FM amount
+    /// maps onto a modulation index between 0 and 5.
+    fn fm_index(&self) -> f32 {
+        if self.fm_routing >= 0.5 {
+            self.fm_amount * 5.0
+        } else {
+            0.0
+        }
+    }
+
+    /// Runs the filtered output through the saturation stage picked by the
+    /// saturation type slider: distortion on the lower half, overdrive on the upper half.
+    /// Either one only kicks in when its amount is above zero.
+    /// The result is clamped to -1 to 1 since heavy distortion overshoots a lot.
+    /// Silence is passed straight through as the distortion formula divides by
+    /// the magnitude of the input.
+    fn saturate(&self, input: f32) -> f32 {
+        if self.saturation_type < 0.5 {
+            if self.distortion > 0.0 && input != 0.0 {
+                distortion(input, self.distortion, self.distortion_volume).clamp(-1.0, 1.0)
+            } else {
+                input
+            }
+        } else if self.drive > 0.0 {
+            // Drive boosts the input by up to 10 times to push it into the overdrive curve.
+            overdrive(input * (1.0 + self.drive * 9.0)).clamp(-1.0, 1.0)
+        } else {
+            input
+        }
+    }
+
+    /// Runs the saturation stage on one channel at the oversampled rate. At 1x
+    /// this is the same as calling saturate directly.
+    fn oversampled_saturate(&mut self, input: f32, channel: usize) -> f32 {
+        let factor = oversampling_factor(self.oversampling);
+        if factor != self.oversampler.factor {
+            self.oversampler.set_factor(factor);
+        }
+        if factor == 1 {
+            return self.saturate(input);
+        }
+
+        let mut samples = self.oversampler.upsample(input, channel);
+        for sample in samples.iter_mut().take(factor) {
+            *sample = self.saturate(*sample);
+        }
+
+        self.oversampler.downsample(&samples, channel)
+    }
+
+    /// Generates the next block of stereo samples into the block buffer,
+    /// advancing the synth by one sample period (1 / sample rate) for each
+    /// sample. Both channels are built together so the envelopes only advance
+    /// once per sample. The buffer is reused, so it only allocates when the
+    /// host's blocks get bigger than it has room for.
+    fn render(&mut self, samples: usize) {
+        let sample = 1.0 / self.sample_rate;
+        self.block.clear();
+
+        for _ in 0..samples {
+            self.advance_arpeggiator(sample);
+            let frame = self.next_sample(sample);
+            self.block.push(frame);
+        }
+
+        let envelope = &self.envelope;
+        self.notes.retain(|voice| !voice.is_finished(envelope));
+    }
+
+    /// Renders a block and hands back a copy of it.
+    #[cfg(test)]
+    fn generate_samples(&mut self, samples: usize) -> Vec<[f32; 2]> {
+        self.render(samples);
+        self.block.clone()
+    }
+
+    /// Sums every voice into the left and right channels and advances each
+    /// voice's oscillator phases and envelope by one sample period. Since only
+    /// the phase increment depends on the frequency, changing the pitch mid-note
+    /// doesn't make the waves jump.
+    fn next_sample(&mut self, sample: f64) -> [f32; 2] {
+        let coefficient = smoothing_coefficient(self.sample_rate);
+        let osc1_volume = self.oscillators[0].volume.next(coefficient);
+        let osc2_volume = self.oscillators[1].volume.next(coefficient);
+        let balance = self.osc_balance.next(coefficient);
+        // In balance mode a single crossfade replaces the two oscillator volumes.
+        let (osc1_volume, osc2_volume) = if self.balance_mode >= 0.5 {
+            (1.0 - balance, balance)
+        } else {
+            (osc1_volume, osc2_volume)
+        };
+        let osc1_detune = self.oscillators[0].detune.next(coefficient);
+        let osc2_detune = self.oscillators[1].detune.next(coefficient);
+        let center_pan = pan_gains(0.5);
+        let fm_index = self.fm_index();
+        // Noise is only generated while a voice is held or releasing, and each
+        // voice scales it by its own envelope level below.
+        let noise_sample = if self.notes.is_empty() || self.noise_enabled < 0.5 {
+            0.0
+        } else {
+            self.noise_generator.next(self.noise)
+        };
+        // Relative noise follows the combined oscillator volume so it keeps
+        // the same balance against the tone however loud the oscillators are.
+        let noise_sample = if self.relative_noise >= 0.5 {
+            noise_sample * (osc1_volume + osc2_volume)
+        } else {
+            noise_sample
+        };
+        let expression = self.expression();
+        let vibrato = expression[0] * self.lfo.value() * 2.0;
+        let pitch = self.bend_semitones() + self.lfo.pitch_offset() + self.sample_hold.pitch_offset() + vibrato + self.glide_offset();
+        let mut filter_level = 0.0;
+        let mut filter_velocity = 0.0;
+        let mut filter_key = 0.0;
+        let mut output = [0.0; 2];
+
+        // Each unison copy is detuned and panned further out the further it is from the middle.
+        let unison_count = self.unison_count.clamp(1, MAX_UNISON);
+        let unison_gain = 1.0 / (unison_count as f32).sqrt();
+        let mut unison = [(0.0, [0.0; 2], [0.0; 2]); MAX_UNISON];
+        for (copy, settings) in unison.iter_mut().enumerate().take(unison_count) {
+            let offset = unison_offset(copy, unison_count);
+            let osc1_pan = pan_gains((self.oscillators[0].pan + 0.5 * offset * self.unison_spread).clamp(0.0, 1.0));
+            let osc2_pan = pan_gains((self.oscillators[1].pan + 0.5 * offset * self.unison_spread).clamp(0.0, 1.0));
+            *settings = (unison_detune(copy, unison_count, self.unison_spread), osc1_pan, osc2_pan);
+        }
+
+        for voice in self.notes.iter_mut() {
+            let gain = velocity_to_peak(voice.velocity, self.velocity_to_envelope);
+            let mut oscillators = [0.0; 2];
+
+            // In split mode notes below the split point only play oscillator 1
+            // and notes from the split point up only play oscillator 2.
+            let (osc1_volume, osc2_volume) = if self.split < 0.5 {
+                (osc1_volume, osc2_volume)
+            } else if voice.midi_note < self.split_point {
+                (osc1_volume, 0.0)
+            } else {
+                (0.0, osc2_volume)
+            };
+
+            let drift = voice.drift.next(self.sample_rate);
+            let osc1_drift = self.drift * DRIFT_CENTS * drift[0];
+            let osc2_drift = self.drift * DRIFT_CENTS * drift[1];
+            let osc1_volume = osc1_volume * (1.0 + self.drift * DRIFT_LEVEL * drift[2]);
+            let osc2_volume = osc2_volume * (1.0 + self.drift * DRIFT_LEVEL * drift[3]);
+
+            for (phases, &(unison_detune, osc1_pan, osc2_pan)) in voice.phases.iter_mut().zip(unison.iter()).take(unison_count) {
+                let freq1 = self.oscillators[0].frequency(voice.midi_note, osc1_detune + osc1_drift + unison_detune, &self.tuning, pitch);
+                let freq2 = self.oscillators[1].frequency(voice.midi_note, osc2_detune + osc2_drift + unison_detune, &self.tuning, pitch);
+                let dt1 = freq1 / self.sample_rate;
+                let dt2 = freq2 / self.sample_rate;
+
+                // Build oscillator 2 wave first, since it can modulate the phase
+                // of oscillator 1 when FM is switched on.
+                // A disabled oscillator skips building its wave, but its phase
+                // keeps running so it still drives hard sync and comes back in step.
+                let wave2 = if self.oscillators[1].is_enabled() {
+                    create_wave(&self.oscillators[1], &self.wavetable, phases[1], dt2, osc2_volume)
+                } else {
+                    0.0
+                };
+                let phase1 = fm_phase(phases[0], wave2, fm_index);
+                let wave1 = if self.oscillators[0].is_enabled() {
+                    create_wave(&self.oscillators[0], &self.wavetable, phase1, dt1, osc1_volume)
+                } else {
+                    0.0
+                };
+
+                // With hard sync on, oscillator 2 restarts its cycle whenever
+                // oscillator 1 wraps, offset by how far oscillator 1 overshot.
+                let wrapped = phases[0] + dt1 >= 1.0;
+                phases[0] = advance_phase(phases[0], dt1);
+                phases[1] = if wrapped && self.hard_sync >= 0.5 {
+                    phases[0] * dt2 / dt1
+                } else {
+                    advance_phase(phases[1], dt2)
+                };
+
+                // Combine the oscillators. Ring and amplitude modulation follow oscillator 1's pan.
+                for (channel, value) in oscillators.iter_mut().enumerate() {
+                    let mix = if self.mix_mode < 0.33 {
+                        wave1 * osc1_pan[channel] + wave2 * osc2_pan[channel]
+                    } else if self.mix_mode < 0.66 {
+                        ring_modulate(wave1, wave2) * osc1_pan[channel]
+                    } else {
+                        amplitude_modulate(wave1, wave2) * osc1_pan[channel]
+                    };
+
+                    *value += unison_gain * mix;
+                }
+            }
+
+            // Build sub oscillator wave.
+            let dt3 = bend_freq(sub_freq(voice.midi_note, &self.tuning), pitch) / self.sample_rate;
+            let sub = if self.sub_wave < 0.5 {
+                create_sine_wave_from_phase(voice.sub_phase, self.sub_level)
+            } else {
+                create_square_wave_blep(voice.sub_phase, dt3, self.sub_level, 0.5, SQUARE_BALANCE)
+            };
+            voice.sub_phase = advance_phase(voice.sub_phase, dt3);
+
+            // There is one filter for every voice, so the newest voice drives
+            // the filter envelope and velocity tracking.
+            filter_velocity = velocity_to_gain(voice.velocity);
+            filter_key = (voice.midi_note as f32 - KEY_TRACK_CENTER as f32) / 12.0;
+            filter_level = if voice.note_on {
+                voice.filter_release_level = get_amplitude(&self.filter_envelope, voice, 1.0);
+                voice.filter_release_level
+            } else {
+                generate_release(&self.filter_envelope, voice, voice.filter_release_level).max(0.0)
+            };
+
+            // Apply this voice's envelope filter. The held level is remembered so
+            // the release can start from wherever the envelope was when the key was lifted.
+            let mut level = 0.0;
+            if voice.note_on {
+                voice.release_level = get_amplitude(&self.envelope, voice, self.master_vol);
+                level = gain * voice.release_level;
+
+                voice.duration += sample;
+            } else {
+                let release_volume = generate_release(&self.envelope, voice, voice.release_level);
+
+                if release_volume > 0.0 {
+                    level = gain * release_volume;
+                }
+
+                voice.end_time += sample;
+            }
+
+            // Noise only mode mutes the oscillators and the sub, leaving the
+            // envelope to shape the noise into percussive hits.
+            let tone = if self.noise_only >= 0.5 { 0.0 } else { 1.0 };
+            for (channel, value) in output.iter_mut().enumerate() {
+                *value += level * (tone * (oscillators[channel] + sub * center_pan[channel]) + noise_sample * center_pan[channel]);
+            }
+        } // end of notes vec loop
+
+        // The gain glides when voices come and go, so it doesn't click.
+        self.normalization_level.target = self.normalization_gain();
+        let gain = self.normalization_level.next(coefficient) * (1.0 + expression[2]);
+        for value in output.iter_mut() {
+            *value *= gain;
+        }
+
+        let lfo_amplitude = self.lfo.amplitude();
+        self.lfo.advance(sample, self.tempo);
+        self.sample_hold.advance(sample);
+        self.advance_glide(sample);
+        self.filter.cutoff_mod = self.filter_env_amount * filter_level
+            + self.velocity_to_cutoff * filter_velocity
+            + Filter::octaves_to_cutoff(self.key_track * filter_key)
+            + expression[1] * 0.5
+            + self.sample_hold.cutoff_offset();
+
+        // The effects always run so their tails carry on if the mix is turned
+        // back up, and the mix crossfades them against the plain oscillator sum.
+        let dry = [output[0] * lfo_amplitude, output[1] * lfo_amplitude];
+        let mut filtered = [0.0; 2];
+        for (channel, value) in output.iter_mut().enumerate() {
+            filtered[channel] = self.filter.process(*value, channel, self.sample_rate);
+            *value = self.oversampled_saturate(filtered[channel], channel);
+        }
+
+        // Auto gain turns the saturated signal back down to the level going in.
+        let gain = self.auto_gain_level.update(filtered, output, self.sample_rate);
+        let gain = if self.auto_gain >= 0.5 { gain } else { 1.0 };
+        for value in output.iter_mut() {
+            *value *= gain * lfo_amplitude;
+        }
+
+        let chorused = self.chorus.process(output, self.sample_rate);
+        let delayed = self.delay.process(chorused, self.sample_rate, self.tempo);
+        let reverberated = self.reverb.process(delayed);
+        let crushed = self.bitcrusher.process(reverberated);
+        let mut mixed = [0.0; 2];
+        for (channel, value) in mixed.iter_mut().enumerate() {
+            *value = dry[channel] * (1.0 - self.effects_mix) + crushed[channel] * self.effects_mix;
+        }
+        let mixed = stereo_width(mixed, self.width * 2.0);
+
+        // DC is taken out before the clipping and limiting, so they still have
+        // the final say over the peak level.
+        let mixed = if self.dc_blocker.is_on() {
+            self.dc_blocker.process(mixed, self.sample_rate)
+        } else {
+            mixed
+        };
+
+        let clipped = if self.soft_clipping >= 0.5 {
+            [soft_clip(mixed[0]), soft_clip(mixed[1])]
+        } else {
+            mixed
+        };
+
+        self.limiter.process(clipped, self.sample_rate)
+    }
+}
+
+/// Shows a time in seconds as milliseconds below a second and as seconds above.
+fn time_text(seconds: f32) -> String {
+    if seconds < 1.0 {
+        format!("{} ms", (seconds * 1000.0).round())
+    } else {
+        format!("{:.2} s", seconds)
+    }
+}
+
+/// Names a MIDI note, with middle C (note 60) as C4.
+fn note_name(note: u8) -> String {
+    const NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    format!("{}{}", NAMES[note as usize % 12], note as i32 / 12 - 1)
+}
+
+/// Describes one plugin parameter: its name, default slider value, how to read
+/// and set it from a slider value, and how to show it in the DAW. The position
+/// in PARAMETERS is the parameter index, so adding a parameter only takes a new
+/// entry here.
+struct Parameter {
+    name: &'static str,
+    default: f32,
+    get: fn(&Crust) -> f32,
+    set: fn(&mut Crust, f32),
+    text: fn(&Crust) -> String,
+}
+
+/// Indices of the oscillator waveform parameters, which preset loading
+/// migrates from version 1.
+const OSC1_WAVEFORM: usize = 0;
+const OSC2_WAVEFORM: usize = 3;
+
+/// Indices of the envelope curve parameters. Presets saved before the decay
+/// and release curves existed give them the attack curve.
+const ATTACK_CURVE: usize = 16;
+const DECAY_CURVE: usize = 108;
+const RELEASE_CURVE: usize = 109;
+
+/// Every parameter the host can see, in index order.
+static PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "Osc 1 waveform",
+        default: 0.0,
+        get: |crust| waveform_value(crust.oscillators[0].wave_index),
+        set: |crust, val| crust.oscillators[0].wave_index = waveform_step(val),
+        text: |crust| WAVEFORM_NAMES[crust.oscillators[0].wave_index].to_string(),
+    },
+    Parameter {
+        name: "Osc 1 volume",
+        default: 0.5,
+        get: |crust| crust.oscillators[0].volume.target,
+        set: |crust, val| crust.oscillators[0].volume.target = val,
+        text: |crust| format!("{}%", (crust.oscillators[0].volume.target * 100.0).round()),
+    },
+    Parameter {
+        name: "Osc 1 detune",
+        default: 0.5,
+        get: |crust| crust.oscillators[0].detune.target / 200.0 + 0.5,
+        set: |crust, val| crust.oscillators[0].detune.target = (val - 0.5) * 200.0,
+        text: |crust| format!("{:+} cents", crust.oscillators[0].detune.target.round()),
+    },
+    Parameter {
+        name: "Osc 2 waveform",
+        default: 0.0,
+        get: |crust| waveform_value(crust.oscillators[1].wave_index),
+        set: |crust, val| crust.oscillators[1].wave_index = waveform_step(val),
+        text: |crust| WAVEFORM_NAMES[crust.oscillators[1].wave_index].to_string(),
+    },
+    Parameter {
+        name: "Osc 2 volume",
+        default: 0.5,
+        get: |crust| crust.oscillators[1].volume.target,
+        set: |crust, val| crust.oscillators[1].volume.target = val,
+        text: |crust| format!("{}%", (crust.oscillators[1].volume.target * 100.0).round()),
+    },
+    Parameter {
+        name: "Osc 2 detune",
+        default: 0.5,
+        get: |crust| crust.oscillators[1].detune.target / 200.0 + 0.5,
+        set: |crust, val| crust.oscillators[1].detune.target = (val - 0.5) * 200.0,
+        text: |crust| format!("{:+} cents", crust.oscillators[1].detune.target.round()),
+    },
+    Parameter {
+        name: "Noise",
+        default: 0.0,
+        get: |crust| crust.noise,
+        set: |crust, val| crust.noise = val,
+        text: |crust| format!("{}%", (crust.noise * 100.0).round()),
+    },
+    Parameter {
+        name: "Attack",
+        default: 0.01,
+        get: |crust| crust.envelope.attack / 5.0,
+        set: |crust, val| crust.envelope.attack = val * 5.0,
+        text: |crust| time_text(crust.envelope.attack),
+    },
+    Parameter {
+        name: "Decay",
+        default: 0.01,
+        get: |crust| crust.envelope.decay / 5.0,
+        set: |crust, val| crust.envelope.decay = val * 5.0,
+        text: |crust| time_text(crust.envelope.decay),
+    },
+    Parameter {
+        name: "Sustain",
+        default: 0.16,
+        get: |crust| crust.envelope.sustain,
+        set: |crust, val| crust.envelope.sustain = val,
+        text: |crust| format!("{}", crust.envelope.sustain),
+    },
+    Parameter {
+        name: "Release",
+        default: 0.028,
+        get: |crust| crust.envelope.release / 5.0,
+        set: |crust, val| crust.envelope.release = val * 5.0,
+        text: |crust| time_text(crust.envelope.release),
+    },
+    Parameter {
+        name: "Master volume",
+        default: 1.0,
+        get: |crust| crust.master_vol,
+        set: |crust, val| crust.master_vol = val,
+        text: |crust| format!("{}%", (crust.master_vol* 100.0).round()),
+    },
+    Parameter {
+        name: "Osc 1 pulse width",
+        default: 0.5,
+        get: |crust| (crust.oscillators[0].pulse_width - 0.05) / 0.9,
+        set: |crust, val| crust.oscillators[0].pulse_width = 0.05 + val * 0.9,
+        text: |crust| format!("{}%", (crust.oscillators[0].pulse_width * 100.0).round()),
+    },
+    Parameter {
+        name: "Osc 2 pulse width",
+        default: 0.5,
+        get: |crust| (crust.oscillators[1].pulse_width - 0.05) / 0.9,
+        set: |crust, val| crust.oscillators[1].pulse_width = 0.05 + val * 0.9,
+        text: |crust| format!("{}%", (crust.oscillators[1].pulse_width * 100.0).round()),
+    },
+    Parameter {
+        name: "Cutoff",
+        default: 1.0,
+        get: |crust| crust.filter.cutoff,
+        set: |crust, val| crust.filter.cutoff = val,
+        text: |crust| format!("{} Hz", crust.filter.cutoff_freq().round()),
+    },
+    Parameter {
+        name: "Resonance",
+        default: 0.0,
+        get: |crust| crust.filter.resonance,
+        set: |crust, val| crust.filter.resonance = val,
+        text: |crust| format!("{}%", (crust.filter.resonance * 100.0).round()),
+    },
+    Parameter {
+        name: "Attack curve",
+        default: 0.0,
+        get: |crust| crust.envelope.attack_curve,
+        set: |crust, val| crust.envelope.attack_curve = val,
+        text: |crust| format!("{}%", (crust.envelope.attack_curve * 100.0).round()),
+    },
+    Parameter {
+        name: "LFO rate",
+        default: 0.5,
+        get: |crust| crust.lfo.rate,
+        set: |crust, val| crust.lfo.rate = val,
+        text: |crust| format!("{:.2} Hz", crust.lfo.rate_hz()),
+    },
+    Parameter {
+        name: "LFO depth",
+        default: 0.0,
+        get: |crust| crust.lfo.depth,
+        set: |crust, val| crust.lfo.depth = val,
+        text: |crust| format!("{}%", (crust.lfo.depth * 100.0).round()),
+    },
+    Parameter {
+        name: "LFO waveform",
+        default: 0.0,
+        get: |crust| crust.lfo.wave_index,
+        set: |crust, val| crust.lfo.wave_index = val,
+        text: |crust| crust.lfo.waveform_name().to_string(),
+    },
+    Parameter {
+        name: "LFO destination",
+        default: 0.0,
+        get: |crust| crust.lfo.destination,
+        set: |crust, val| crust.lfo.destination = val,
+        text: |crust| if crust.lfo.modulates_pitch() { "Pitch".to_string() } else { "Amplitude".to_string() },
+    },
+    Parameter {
+        name: "Polyphony",
+        default: 15.0 / 31.0,
+        get: |crust| (crust.max_voices - 1) as f32 / 31.0,
+        set: |crust, val| crust.max_voices = 1 + (val * 31.0).round() as usize,
+        text: |crust| format!("{} voices", crust.max_voices),
+    },
+    Parameter {
+        name: "Glide",
+        default: 0.0,
+        get: |crust| crust.glide_time / 2.0,
+        set: |crust, val| crust.glide_time = val * 2.0,
+        text: |crust| time_text(crust.glide_time),
+    },
+    Parameter {
+        name: "Osc 1 pan",
+        default: 0.5,
+        get: |crust| crust.oscillators[0].pan,
+        set: |crust, val| crust.oscillators[0].pan = val,
+        text: |crust| format!("{}", ((crust.oscillators[0].pan - 0.5) * 200.0).round()),
+    },
+    Parameter {
+        name: "Osc 2 pan",
+        default: 0.5,
+        get: |crust| crust.oscillators[1].pan,
+        set: |crust, val| crust.oscillators[1].pan = val,
+        text: |crust| format!("{}", ((crust.oscillators[1].pan - 0.5) * 200.0).round()),
+    },
+    Parameter {
+        name: "Sub level",
+        default: 0.0,
+        get: |crust| crust.sub_level,
+        set: |crust, val| crust.sub_level = val,
+        text: |crust| format!("{}%", (crust.sub_level * 100.0).round()),
+    },
+    Parameter {
+        name: "Sub waveform",
+        default: 0.0,
+        get: |crust| crust.sub_wave,
+        set: |crust, val| crust.sub_wave = val,
+        text: |crust| if crust.sub_wave < 0.5 { "Sine".to_string() } else { "Square".to_string() },
+    },
+    Parameter {
+        name: "FM amount",
+        default: 0.0,
+        get: |crust| crust.fm_amount,
+        set: |crust, val| crust.fm_amount = val,
+        text: |crust| format!("{:.2}", crust.fm_amount * 5.0),
+    },
+    Parameter {
+        name: "FM osc 2 to osc 1",
+        default: 0.0,
+        get: |crust| crust.fm_routing,
+        set: |crust, val| crust.fm_routing = val,
+        text: |crust| if crust.fm_routing >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Osc mix mode",
+        default: 0.0,
+        get: |crust| crust.mix_mode,
+        set: |crust, val| crust.mix_mode = val,
+        text: |crust| if crust.mix_mode < 0.33 {
+            "Sum".to_string()
+        } else if crust.mix_mode < 0.66 {
+            "Ring mod".to_string()
+        } else {
+            "AM".to_string()
+        },
+    },
+    Parameter {
+        name: "Unison voices",
+        default: 0.0,
+        get: |crust| (crust.unison_count - 1) as f32 / 6.0,
+        set: |crust, val| crust.unison_count = 1 + (val * 6.0).round() as usize,
+        text: |crust| format!("{}", crust.unison_count),
+    },
+    Parameter {
+        name: "Unison spread",
+        default: 0.0,
+        get: |crust| crust.unison_spread,
+        set: |crust, val| crust.unison_spread = val,
+        text: |crust| format!("{} cents", (crust.unison_spread * UNISON_SPREAD_CENTS).round()),
+    },
+    Parameter {
+        name: "Distortion",
+        default: 0.0,
+        get: |crust| crust.distortion,
+        set: |crust, val| crust.distortion = val,
+        text: |crust| format!("{}%", (crust.distortion * 100.0).round()),
+    },
+    Parameter {
+        name: "Distortion volume",
+        default: 1.0,
+        get: |crust| crust.distortion_volume,
+        set: |crust, val| crust.distortion_volume = val,
+        text: |crust| format!("{}%", (crust.distortion_volume * 100.0).round()),
+    },
+    Parameter {
+        name: "Saturation type",
+        default: 0.0,
+        get: |crust| crust.saturation_type,
+        set: |crust, val| crust.saturation_type = val,
+        text: |crust| if crust.saturation_type < 0.5 { "Distortion".to_string() } else { "Overdrive".to_string() },
+    },
+    Parameter {
+        name: "Drive",
+        default: 0.0,
+        get: |crust| crust.drive,
+        set: |crust, val| crust.drive = val,
+        text: |crust| format!("{}%", (crust.drive * 100.0).round()),
+    },
+    Parameter {
+        name: "Filter type",
+        default: 0.0,
+        get: |crust| crust.filter.filter_type,
+        set: |crust, val| crust.filter.filter_type = val,
+        text: |crust| crust.filter.type_name(),
+    },
+    Parameter {
+        name: "MIDI channel",
+        default: 0.0,
+        get: |crust| crust.midi_channel,
+        set: |crust, val| crust.midi_channel = val,
+        text: |crust| match crust.channel_setting() {
+            0 => "Omni".to_string(),
+            channel => format!("{}", channel),
+        },
+    },
+    Parameter {
+        name: "Filter env attack",
+        default: 0.01,
+        get: |crust| crust.filter_envelope.attack / 5.0,
+        set: |crust, val| crust.filter_envelope.attack = val * 5.0,
+        text: |crust| time_text(crust.filter_envelope.attack),
+    },
+    Parameter {
+        name: "Filter env decay",
+        default: 0.01,
+        get: |crust| crust.filter_envelope.decay / 5.0,
+        set: |crust, val| crust.filter_envelope.decay = val * 5.0,
+        text: |crust| time_text(crust.filter_envelope.decay),
+    },
+    Parameter {
+        name: "Filter env sustain",
+        default: 0.16,
+        get: |crust| crust.filter_envelope.sustain,
+        set: |crust, val| crust.filter_envelope.sustain = val,
+        text: |crust| format!("{}", crust.filter_envelope.sustain),
+    },
+    Parameter {
+        name: "Filter env release",
+        default: 0.028,
+        get: |crust| crust.filter_envelope.release / 5.0,
+        set: |crust, val| crust.filter_envelope.release = val * 5.0,
+        text: |crust| time_text(crust.filter_envelope.release),
+    },
+    Parameter {
+        name: "Filter env amount",
+        default: 0.0,
+        get: |crust| crust.filter_env_amount,
+        set: |crust, val| crust.filter_env_amount = val,
+        text: |crust| format!("{}%", (crust.filter_env_amount * 100.0).round()),
+    },
+    Parameter {
+        name: "Master tuning",
+        default: 25.0 / 51.0,
+        get: |crust| (crust.tuning.reference - 415.0) / 51.0,
+        set: |crust, val| crust.tuning.reference = 415.0 + val * 51.0,
+        text: |crust| format!("{:.1} Hz", crust.tuning.reference),
+    },
+    Parameter {
+        name: "Noise color",
+        default: 1.0,
+        get: |crust| crust.noise_generator.color,
+        set: |crust, val| crust.noise_generator.color = val,
+        text: |crust| crust.noise_generator.color_name(),
+    },
+    Parameter {
+        name: "Delay time",
+        default: 0.125,
+        get: |crust| crust.delay.time / MAX_DELAY_TIME as f32,
+        set: |crust, val| crust.delay.time = val * MAX_DELAY_TIME as f32,
+        text: |crust| format!("{} ms", (crust.delay.time * 1000.0).round()),
+    },
+    Parameter {
+        name: "Delay feedback",
+        default: 0.3,
+        get: |crust| crust.delay.feedback,
+        set: |crust, val| crust.delay.feedback = val,
+        text: |crust| format!("{}%", (crust.delay.feedback * 100.0).round()),
+    },
+    Parameter {
+        name: "Delay mix",
+        default: 0.0,
+        get: |crust| crust.delay.mix,
+        set: |crust, val| crust.delay.mix = val,
+        text: |crust| format!("{}%", (crust.delay.mix * 100.0).round()),
+    },
+    Parameter {
+        name: "Chorus rate",
+        default: 0.3,
+        get: |crust| crust.chorus.rate,
+        set: |crust, val| crust.chorus.rate = val,
+        text: |crust| format!("{:.2} Hz", crust.chorus.rate_hz()),
+    },
+    Parameter {
+        name: "Chorus depth",
+        default: 0.5,
+        get: |crust| crust.chorus.depth,
+        set: |crust, val| crust.chorus.depth = val,
+        text: |crust| format!("{}%", (crust.chorus.depth * 100.0).round()),
+    },
+    Parameter {
+        name: "Chorus mix",
+        default: 0.0,
+        get: |crust| crust.chorus.mix,
+        set: |crust, val| crust.chorus.mix = val,
+        text: |crust| format!("{}%", (crust.chorus.mix * 100.0).round()),
+    },
+    Parameter {
+        name: "Bit depth",
+        default: 1.0,
+        get: |crust| crust.bitcrusher.bit_depth,
+        set: |crust, val| crust.bitcrusher.bit_depth = val,
+        text: |crust| format!("{} bits", crust.bitcrusher.bits()),
+    },
+    Parameter {
+        name: "Downsample",
+        default: 0.0,
+        get: |crust| crust.bitcrusher.downsample,
+        set: |crust, val| crust.bitcrusher.downsample = val,
+        text: |crust| format!("{}x", crust.bitcrusher.factor()),
+    },
+    Parameter {
+        name: "Voice mode",
+        default: 0.0,
+        get: |crust| crust.voice_mode,
+        set: |crust, val| crust.voice_mode = val,
+        text: |crust| if crust.is_legato() { "Mono legato".to_string() } else { "Poly".to_string() },
+    },
+    Parameter {
+        name: "LFO retrigger",
+        default: 0.0,
+        get: |crust| crust.lfo.retrigger,
+        set: |crust, val| crust.lfo.retrigger = val,
+        text: |crust| if crust.lfo.retriggers() { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Velocity to cutoff",
+        default: 0.0,
+        get: |crust| crust.velocity_to_cutoff,
+        set: |crust, val| crust.velocity_to_cutoff = val,
+        text: |crust| format!("{}%", (crust.velocity_to_cutoff * 100.0).round()),
+    },
+    Parameter {
+        name: "Filter key tracking",
+        default: 0.0,
+        get: |crust| crust.key_track,
+        set: |crust, val| crust.key_track = val,
+        text: |crust| format!("{}%", (crust.key_track * 100.0).round()),
+    },
+    Parameter {
+        name: "Arpeggiator",
+        default: 0.0,
+        get: |crust| crust.arpeggiator.enabled,
+        set: |crust, val| crust.arpeggiator.enabled = val,
+        text: |crust| if crust.arpeggiator.is_on() { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Arp rate",
+        default: 0.5,
+        get: |crust| crust.arpeggiator.rate,
+        set: |crust, val| crust.arpeggiator.rate = val,
+        text: |crust| format!("{:.2} Hz", crust.arpeggiator.rate_hz()),
+    },
+    Parameter {
+        name: "Arp mode",
+        default: 0.0,
+        get: |crust| crust.arpeggiator.mode,
+        set: |crust, val| crust.arpeggiator.mode = val,
+        text: |crust| crust.arpeggiator.mode_name(),
+    },
+    Parameter {
+        name: "Arp octaves",
+        default: 0.0,
+        get: |crust| crust.arpeggiator.octaves,
+        set: |crust, val| crust.arpeggiator.octaves = val,
+        text: |crust| format!("{}", crust.arpeggiator.octave_count()),
+    },
+    Parameter {
+        name: "LFO sync",
+        default: 0.0,
+        get: |crust| crust.lfo.sync,
+        set: |crust, val| crust.lfo.sync = val,
+        text: |crust| sync_text(crust.lfo.sync),
+    },
+    Parameter {
+        name: "Delay sync",
+        default: 0.0,
+        get: |crust| crust.delay.sync,
+        set: |crust, val| crust.delay.sync = val,
+        text: |crust| sync_text(crust.delay.sync),
+    },
+    Parameter {
+        name: "Soft clipping",
+        default: 0.0,
+        get: |crust| crust.soft_clipping,
+        set: |crust, val| crust.soft_clipping = val,
+        text: |crust| if crust.soft_clipping >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Voice normalization",
+        default: 0.0,
+        get: |crust| crust.normalization,
+        set: |crust, val| crust.normalization = val,
+        text: |crust| if crust.normalization < 0.33 {
+            "Off".to_string()
+        } else if crust.normalization < 0.66 {
+            "Square root".to_string()
+        } else {
+            "Full".to_string()
+        },
+    },
+    Parameter {
+        name: "Aftertouch destination",
+        default: 0.0,
+        get: |crust| crust.aftertouch_target,
+        set: |crust, val| crust.aftertouch_target = val,
+        text: |crust| EXPRESSION_TARGETS[expression_target(crust.aftertouch_target)].to_string(),
+    },
+    Parameter {
+        name: "Mod wheel destination",
+        default: 0.0,
+        get: |crust| crust.mod_wheel_target,
+        set: |crust, val| crust.mod_wheel_target = val,
+        text: |crust| EXPRESSION_TARGETS[expression_target(crust.mod_wheel_target)].to_string(),
+    },
+    Parameter {
+        name: "Hard sync",
+        default: 0.0,
+        get: |crust| crust.hard_sync,
+        set: |crust, val| crust.hard_sync = val,
+        text: |crust| if crust.hard_sync >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Osc 1 table position",
+        default: 0.0,
+        get: |crust| crust.oscillators[0].table_position,
+        set: |crust, val| crust.oscillators[0].table_position = val,
+        text: |crust| format!("{}%", (crust.oscillators[0].table_position * 100.0).round()),
+    },
+    Parameter {
+        name: "Osc 2 table position",
+        default: 0.0,
+        get: |crust| crust.oscillators[1].table_position,
+        set: |crust, val| crust.oscillators[1].table_position = val,
+        text: |crust| format!("{}%", (crust.oscillators[1].table_position * 100.0).round()),
+    },
+    Parameter {
+        name: "Effects mix",
+        default: 1.0,
+        get: |crust| crust.effects_mix,
+        set: |crust, val| crust.effects_mix = val,
+        text: |crust| format!("{}%", (crust.effects_mix * 100.0).round()),
+    },
+    Parameter {
+        name: "Osc 1 transpose",
+        default: 0.5,
+        get: |crust| crust.oscillators[0].transpose as f32 / 72.0 + 0.5,
+        set: |crust, val| crust.oscillators[0].transpose = ((val - 0.5) * 72.0).round() as i32,
+        text: |crust| format!("{:+} semitones", crust.oscillators[0].transpose),
+    },
+    Parameter {
+        name: "Osc 2 transpose",
+        default: 0.5,
+        get: |crust| crust.oscillators[1].transpose as f32 / 72.0 + 0.5,
+        set: |crust, val| crust.oscillators[1].transpose = ((val - 0.5) * 72.0).round() as i32,
+        text: |crust| format!("{:+} semitones", crust.oscillators[1].transpose),
+    },
+    Parameter {
+        name: "Osc 1 phase",
+        default: 0.0,
+        get: |crust| crust.oscillators[0].phase_offset,
+        set: |crust, val| crust.oscillators[0].phase_offset = val,
+        text: |crust| format!("{}°", (crust.oscillators[0].phase_offset * 360.0).round()),
+    },
+    Parameter {
+        name: "Osc 2 phase",
+        default: 0.0,
+        get: |crust| crust.oscillators[1].phase_offset,
+        set: |crust, val| crust.oscillators[1].phase_offset = val,
+        text: |crust| format!("{}°", (crust.oscillators[1].phase_offset * 360.0).round()),
+    },
+    Parameter {
+        name: "Osc 1 square level",
+        default: 0.0,
+        get: |crust| crust.oscillators[0].full_scale_square,
+        set: |crust, val| crust.oscillators[0].full_scale_square = val,
+        text: |crust| if crust.oscillators[0].square_level() == 1.0 { "Full scale".to_string() } else { "Balanced".to_string() },
+    },
+    Parameter {
+        name: "Osc 2 square level",
+        default: 0.0,
+        get: |crust| crust.oscillators[1].full_scale_square,
+        set: |crust, val| crust.oscillators[1].full_scale_square = val,
+        text: |crust| if crust.oscillators[1].square_level() == 1.0 { "Full scale".to_string() } else { "Balanced".to_string() },
+    },
+    Parameter {
+        name: "Glide legato",
+        default: 0.0,
+        get: |crust| crust.glide_legato,
+        set: |crust, val| crust.glide_legato = val,
+        text: |crust| if crust.glide_legato >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Osc balance mode",
+        default: 0.0,
+        get: |crust| crust.balance_mode,
+        set: |crust, val| crust.balance_mode = val,
+        text: |crust| if crust.balance_mode >= 0.5 { "Balance".to_string() } else { "Volumes".to_string() },
+    },
+    Parameter {
+        name: "Osc balance",
+        default: 0.5,
+        get: |crust| crust.osc_balance.target,
+        set: |crust, val| crust.osc_balance.target = val,
+        text: |crust| format!("{}% / {}%", ((1.0 - crust.osc_balance.target) * 100.0).round(), (crust.osc_balance.target * 100.0).round()),
+    },
+    Parameter {
+        name: "Reverb size",
+        default: 0.5,
+        get: |crust| crust.reverb.room_size,
+        set: |crust, val| crust.reverb.room_size = val,
+        text: |crust| format!("{}%", (crust.reverb.room_size * 100.0).round()),
+    },
+    Parameter {
+        name: "Reverb damping",
+        default: 0.5,
+        get: |crust| crust.reverb.damping,
+        set: |crust, val| crust.reverb.damping = val,
+        text: |crust| format!("{}%", (crust.reverb.damping * 100.0).round()),
+    },
+    Parameter {
+        name: "Reverb mix",
+        default: 0.0,
+        get: |crust| crust.reverb.mix,
+        set: |crust, val| crust.reverb.mix = val,
+        text: |crust| format!("{}%", (crust.reverb.mix * 100.0).round()),
+    },
+    Parameter {
+        name: "Stereo width",
+        default: 0.5,
+        get: |crust| crust.width,
+        set: |crust, val| crust.width = val,
+        text: |crust| format!("{}%", (crust.width * 200.0).round()),
+    },
+    Parameter {
+        name: "Oversampling",
+        default: 0.0,
+        get: |crust| crust.oversampling,
+        set: |crust, val| crust.oversampling = val,
+        text: |crust| format!("{}x", oversampling_factor(crust.oversampling)),
+    },
+    Parameter {
+        name: "Velocity to envelope",
+        default: 1.0,
+        get: |crust| crust.velocity_to_envelope,
+        set: |crust, val| crust.velocity_to_envelope = val,
+        text: |crust| format!("{}%", (crust.velocity_to_envelope * 100.0).round()),
+    },
+    Parameter {
+        name: "Latch",
+        default: 0.0,
+        get: |crust| crust.latch,
+        set: |crust, val| crust.set_latch(val),
+        text: |crust| if crust.is_latched() { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Bend range",
+        default: 2.0 / 24.0,
+        get: |crust| crust.bend_range / 24.0,
+        set: |crust, val| crust.bend_range = (val * 24.0).round(),
+        text: |crust| format!("±{} semitones", crust.bend_range),
+    },
+    Parameter {
+        name: "Noise only",
+        default: 0.0,
+        get: |crust| crust.noise_only,
+        set: |crust, val| crust.noise_only = val,
+        text: |crust| if crust.noise_only >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Drive auto gain",
+        default: 0.0,
+        get: |crust| crust.auto_gain,
+        set: |crust, val| crust.auto_gain = val,
+        text: |crust| if crust.auto_gain >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Noise level",
+        default: 0.0,
+        get: |crust| crust.relative_noise,
+        set: |crust, val| crust.relative_noise = val,
+        text: |crust| if crust.relative_noise >= 0.5 { "Relative to oscs".to_string() } else { "Independent".to_string() },
+    },
+    Parameter {
+        name: "Keyboard split",
+        default: 0.0,
+        get: |crust| crust.split,
+        set: |crust, val| crust.split = val,
+        text: |crust| if crust.split >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Split point",
+        default: 60.0 / 127.0,
+        get: |crust| crust.split_point as f32 / 127.0,
+        set: |crust, val| crust.split_point = (val * 127.0).round() as u8,
+        text: |crust| note_name(crust.split_point),
+    },
+    Parameter {
+        name: "Drift",
+        default: 0.0,
+        get: |crust| crust.drift,
+        set: |crust, val| crust.drift = val,
+        text: |crust| format!("{}%", (crust.drift * 100.0).round()),
+    },
+    Parameter {
+        name: "Osc 1 morph",
+        default: 0.0,
+        get: |crust| crust.oscillators[0].morph,
+        set: |crust, val| crust.oscillators[0].morph = val,
+        text: |crust| if crust.oscillators[0].morph >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Osc 2 morph",
+        default: 0.0,
+        get: |crust| crust.oscillators[1].morph,
+        set: |crust, val| crust.oscillators[1].morph = val,
+        text: |crust| if crust.oscillators[1].morph >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Osc 1 morph position",
+        default: 0.0,
+        get: |crust| crust.oscillators[0].morph_position,
+        set: |crust, val| crust.oscillators[0].morph_position = val,
+        text: |crust| morph_text(crust.oscillators[0].morph_position),
+    },
+    Parameter {
+        name: "Osc 2 morph position",
+        default: 0.0,
+        get: |crust| crust.oscillators[1].morph_position,
+        set: |crust, val| crust.oscillators[1].morph_position = val,
+        text: |crust| morph_text(crust.oscillators[1].morph_position),
+    },
+    Parameter {
+        name: "Osc 1 enabled",
+        default: 1.0,
+        get: |crust| crust.oscillators[0].enabled,
+        set: |crust, val| crust.oscillators[0].enabled = val,
+        text: |crust| if crust.oscillators[0].is_enabled() { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Osc 2 enabled",
+        default: 1.0,
+        get: |crust| crust.oscillators[1].enabled,
+        set: |crust, val| crust.oscillators[1].enabled = val,
+        text: |crust| if crust.oscillators[1].is_enabled() { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Noise enabled",
+        default: 1.0,
+        get: |crust| crust.noise_enabled,
+        set: |crust, val| crust.noise_enabled = val,
+        text: |crust| if crust.noise_enabled >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Osc 1 fixed freq",
+        default: 0.0,
+        get: |crust| crust.oscillators[0].fixed,
+        set: |crust, val| crust.oscillators[0].fixed = val,
+        text: |crust| if crust.oscillators[0].fixed >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Osc 2 fixed freq",
+        default: 0.0,
+        get: |crust| crust.oscillators[1].fixed,
+        set: |crust, val| crust.oscillators[1].fixed = val,
+        text: |crust| if crust.oscillators[1].fixed >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Osc 1 frequency",
+        default: 0.5,
+        get: |crust| crust.oscillators[0].fixed_freq,
+        set: |crust, val| crust.oscillators[0].fixed_freq = val,
+        text: |crust| format!("{} Hz", crust.oscillators[0].fixed_hz().round()),
+    },
+    Parameter {
+        name: "Osc 2 frequency",
+        default: 0.5,
+        get: |crust| crust.oscillators[1].fixed_freq,
+        set: |crust, val| crust.oscillators[1].fixed_freq = val,
+        text: |crust| format!("{} Hz", crust.oscillators[1].fixed_hz().round()),
+    },
+    Parameter {
+        name: "Limiter",
+        default: 0.0,
+        get: |crust| crust.limiter.enabled,
+        set: |crust, val| crust.limiter.enabled = val,
+        text: |crust| if crust.limiter.is_on() { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Limiter threshold",
+        default: 1.0,
+        get: |crust| crust.limiter.threshold,
+        set: |crust, val| crust.limiter.threshold = val,
+        text: |crust| format!("{:.1} dB", crust.limiter.threshold_db()),
+    },
+    Parameter {
+        name: "Limiter release",
+        default: 0.5,
+        get: |crust| crust.limiter.release,
+        set: |crust, val| crust.limiter.release = val,
+        text: |crust| time_text(crust.limiter.release_time()),
+    },
+    Parameter {
+        name: "Decay curve",
+        default: 0.0,
+        get: |crust| crust.envelope.decay_curve,
+        set: |crust, val| crust.envelope.decay_curve = val,
+        text: |crust| format!("{}%", (crust.envelope.decay_curve * 100.0).round()),
+    },
+    Parameter {
+        name: "Release curve",
+        default: 0.0,
+        get: |crust| crust.envelope.release_curve,
+        set: |crust, val| crust.envelope.release_curve = val,
+        text: |crust| format!("{}%", (crust.envelope.release_curve * 100.0).round()),
+    },
+    Parameter {
+        name: "DC filter",
+        default: 0.0,
+        get: |crust| crust.dc_blocker.enabled,
+        set: |crust, val| crust.dc_blocker.enabled = val,
+        text: |crust| if crust.dc_blocker.is_on() { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "Phase reset",
+        default: 0.0,
+        get: |crust| crust.phase_reset,
+        set: |crust, val| crust.phase_reset = val,
+        text: |crust| if crust.phase_reset >= 0.5 { "On".to_string() } else { "Off".to_string() },
+    },
+    Parameter {
+        name: "S&H rate",
+        default: 0.5,
+        get: |crust| crust.sample_hold.rate,
+        set: |crust, val| crust.sample_hold.rate = val,
+        text: |crust| format!("{:.2} Hz", crust.sample_hold.rate_hz()),
+    },
+    Parameter {
+        name: "S&H depth",
+        default: 0.0,
+        get: |crust| crust.sample_hold.depth,
+        set: |crust, val| crust.sample_hold.depth = val,
+        text: |crust| format!("{}%", (crust.sample_hold.depth * 100.0).round()),
+    },
+    Parameter {
+        name: "S&H destination",
+        default: 0.0,
+        get: |crust| crust.sample_hold.destination,
+        set: |crust, val| crust.sample_hold.destination = val,
+        text: |crust| if crust.sample_hold.modulates_pitch() { "Pitch".to_string() } else { "Cutoff".to_string() },
+    },
+];
+
+/// Slider values shared with an editor running on its own thread. The synth
+/// writes back every value the host sets, and values the editor sets are
+/// picked up by the synth at the start of its next block.
+pub struct SharedParameters {
+    values: Vec<AtomicU32>,
+    changed: Vec<AtomicBool>,
+}
+
+/// Default SharedParameters values, every parameter at its default.
+impl Default for SharedParameters {
+    fn default() -> SharedParameters {
+        SharedParameters {
+            values: PARAMETERS.iter().map(|parameter| AtomicU32::new(parameter.default.to_bits())).collect(),
+            changed: PARAMETERS.iter().map(|_| AtomicBool::new(false)).collect(),
+        }
+    }
+}
+
+impl SharedParameters {
+    /// Latest slider value from the host or the editor, 0 for an unknown index.
+    pub fn get(&self, index: usize) -> f32 {
+        self.values.get(index).map_or(0.0, |value| f32::from_bits(value.load(Ordering::Relaxed)))
+    }
+
+    /// Sets a slider value from the editor for the synth to pick up.
+    pub fn set(&self, index: usize, value: f32) {
+        if let (Some(stored), Some(changed)) = (self.values.get(index), self.changed.get(index)) {
+            stored.store(value.to_bits(), Ordering::Relaxed);
+            changed.store(true, Ordering::Release);
+        }
+    }
+
+    /// Records a value the synth has already applied.
+    fn store(&self, index: usize, value: f32) {
+        if let Some(stored) = self.values.get(index) {
+            stored.store(value.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// The editor's value for a parameter if the synth has yet to pick it up.
+    fn pending(&self, index: usize) -> Option<f32> {
+        match self.changed.get(index) {
+            Some(changed) if changed.load(Ordering::Acquire) => Some(self.get(index)),
+            _ => None,
+        }
+    }
+
+    /// Takes the editor's value for a parameter, if it changed since last taken.
+    fn take(&self, index: usize) -> Option<f32> {
+        match self.changed.get(index) {
+            Some(changed) if changed.swap(false, Ordering::Acquire) => Some(self.get(index)),
+            _ => None,
+        }
+    }
+}
+
+/// Implements all methods required for the Plugin trait of the vst crate.
+impl Plugin for Crust {
+    /// Keeps the host callback so the synth can ask for the tempo.
+    fn new(host: HostCallback) -> Crust {
+        Crust {
+            host: Some(host),
+            ..Default::default()
+        }
+    }
+
+    fn get_info(&self) -> Info {
+        Info {
+            name: "Crust".to_string(),
+            vendor: "Randy Rollofson".to_string(),
+            unique_id: UNIQUE_ID,
+            version: VERSION,
+            inputs: 0,
+            outputs: 2,
+            presets: FACTORY_PRESETS.len() as i32,
+            parameters: PARAMETERS.len() as i32,
+            category: Category::Synth,
+            preset_chunks: true,
+            initial_delay: self.latency(),
+            f64_precision: true,
+            ..Default::default()
+        }
+    }
+
+    /// Loads one of the factory presets, starting from the default sound.
+    fn change_preset(&mut self, preset: i32) {
+        if preset < 0 || preset as usize >= FACTORY_PRESETS.len() {
+            return;
+        }
+
+        for (index, parameter) in PARAMETERS.iter().enumerate() {
+            self.set_parameter(index as i32, parameter.default);
+        }
+        for &(index, value) in FACTORY_PRESETS[preset as usize].parameters {
+            self.set_parameter(index, value);
+        }
+        self.preset = preset;
+    }
+
+    /// The factory preset that was loaded last.
+    fn get_preset_num(&self) -> i32 {
+        self.preset
+    }
+
+    /// Name of the factory preset shown in the host's program list.
+    fn get_preset_name(&self, preset: i32) -> String {
+        FACTORY_PRESETS
+            .get(preset as usize)
+            .map_or_else(|| "".to_string(), |p| p.name.to_string())
+    }
+
+    /// Gets the values that will be used in the plugin UI in the DAW.
+    /// A value the editor has set but the synth hasn't applied yet is reported
+    /// straight away, so the host and the editor agree.
+    fn get_parameter(&self, index: i32) -> f32 {
+        if let Some(value) = self.shared.pending(index as usize) {
+            return value;
+        }
+        PARAMETERS.get(index as usize).map_or(0.0, |parameter| (parameter.get)(self))
+    }
+
+    /// Sets each value based on slider values in UI in the DAW.
+    /// Values outside 0 to 1 are clamped and NaN or infinite values are ignored,
+    /// so a bad value from the host can't push a setting out of its range.
+    fn set_parameter(&mut self, index: i32, val: f32) {
+        if !val.is_finite() {
+            return;
+        }
+
+        if let Some(parameter) = PARAMETERS.get(index as usize) {
+            (parameter.set)(self, val.clamp(0.0, 1.0));
+            self.shared.store(index as usize, (parameter.get)(self));
+        }
+    }
+
+    /// Tells the host how long to keep processing after the input stops so
+    /// bounces don't cut off the release and echoes. 1 means there is no tail.
+    fn get_tail_size(&self) -> isize {
+        (self.tail_samples().ceil() as isize).max(1)
+    }
+
+    /// There is no custom editor yet, so hosts show their own panel of the
+    /// parameters. An editor would hold the handle from shared_parameters,
+    /// which get_parameter and set_parameter keep in step with the synth.
+    fn get_editor(&mut self) -> Option<&mut dyn Editor> {
+        None
+    }
+
+    /// Every parameter can be automated. The waveform sliders step between
+    /// waveforms, so automating them switches cleanly from one to the next.
+    fn can_be_automated(&self, index: i32) -> bool {
+        index >= 0 && (index as usize) < PARAMETERS.len()
+    }
+
+    /// The text that will appear under each slider in the UI.
+    fn get_parameter_name(&self, index: i32) -> String {
+        PARAMETERS.get(index as usize).map_or(String::new(), |parameter| parameter.name.to_string())
+    }
+
+    /// Determines how to display the data based on the slider position in the UI.
+    fn get_parameter_text(&self, index: i32) -> String {
+        PARAMETERS.get(index as usize).map_or(String::new(), |parameter| (parameter.text)(self))
+    }
+
+    /// Entery point of the program.
+    /// Handles incoming midi events.
+    fn process_events(&mut self, events: &Events) {
+        for event in events.events() {
+            match event {
+                Event::Midi(ev) => self.process_midi_data(ev.data),
+                _ => (),
+            }
+        }
+    }
+
+    /// Saves every parameter for the host. The data is a version byte, the
+    /// number of parameters, then each slider value as a little endian f32,
+    /// followed by the 12 cents offsets of the tuning table.
+    fn get_preset_data(&mut self) -> Vec<u8> {
+        let count = PARAMETERS.len();
+        let mut data = vec![PRESET_VERSION];
+        data.extend_from_slice(&(count as u32).to_le_bytes());
+        for index in 0..count {
+            data.extend_from_slice(&self.get_parameter(index as i32).to_le_bytes());
+        }
+        for cents in self.tuning.cents.iter() {
+            data.extend_from_slice(&cents.to_le_bytes());
+        }
+
+        let mut mappings: Vec<(&u8, &i32)> = self.cc_map.iter().collect();
+        mappings.sort();
+        data.extend_from_slice(&(mappings.len() as u32).to_le_bytes());
+        for (&controller, &index) in mappings {
+            data.push(controller);
+            data.extend_from_slice(&index.to_le_bytes());
+        }
+
+        data
+    }
+
+    /// Restores parameters saved by get_preset_data. Presets saved before a
+    /// parameter was added leave that parameter untouched, and data with an
+    /// unknown version is ignored. Version 1 waveform sliders are moved onto
+    /// the same waveform on the five position slider, and presets from before
+    /// the decay and release curves give them the shared attack curve.
+    /// The tuning table and controller mappings are only loaded if the data
+    /// has them.
+    fn load_preset_data(&mut self, data: &[u8]) {
+        let version = match data.first() {
+            Some(&version) if data.len() >= 5 && (1..=PRESET_VERSION).contains(&version) => version,
+            _ => return,
+        };
+
+        let count = u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as usize;
+        let to_f32 = |bytes: &[u8]| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let parameters: Vec<f32> = data[5..].chunks_exact(4).take(count).map(to_f32).collect();
+        for (index, &value) in parameters.iter().enumerate().take(PARAMETERS.len()) {
+            self.set_parameter(index as i32, value);
+        }
+        if version == 1 {
+            for &index in [OSC1_WAVEFORM, OSC2_WAVEFORM].iter() {
+                if let Some(&value) = parameters.get(index) {
+                    self.set_parameter(index as i32, waveform_value(legacy_waveform_step(value)));
+                }
+            }
+        }
+        if count <= DECAY_CURVE {
+            if let Some(&curve) = parameters.get(ATTACK_CURVE) {
+                self.set_parameter(DECAY_CURVE as i32, curve);
+                self.set_parameter(RELEASE_CURVE as i32, curve);
+            }
+        }
+
+        let rest = data.get(5 + count * 4..).unwrap_or(&[]);
+        let table_size = self.tuning.cents.len() * 4;
+        if rest.len() < table_size {
+            return;
+        }
+        for (cents, bytes) in self.tuning.cents.iter_mut().zip(rest.chunks_exact(4)) {
+            *cents = to_f32(bytes);
+        }
+
+        let rest = &rest[table_size..];
+        if rest.len() < 4 {
+            return;
+        }
+        let mappings = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        self.cc_map.clear();
+        for entry in rest[4..].chunks_exact(5).take(mappings) {
+            let index = i32::from_le_bytes([entry[1], entry[2], entry[3], entry[4]]);
+            self.map_controller(entry[0], Some(index));
+        }
+    }
+
+    /// Stores the sample rate the host is running at so that pitches and
+    /// envelope timings stay correct at any rate.
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate as f64;
+        self.chorus.resize(self.sample_rate);
+        self.delay.resize(self.sample_rate);
+        self.reverb.resize(self.sample_rate);
+        self.limiter.reset();
+    }
+
+    /// Makes room in the block buffer for the host's biggest block up front,
+    /// so process doesn't have to allocate.
+    fn set_block_size(&mut self, size: i64) {
+        let size = size.max(0) as usize;
+        if size > self.block.capacity() {
+            self.block.reserve(size - self.block.len());
+        }
+    }
+
+    /// Method for outputting audio.
+    /// Loops through the buffer and outputs an f32 value between 0 and 1
+    /// for each sample. The first channel is the left and the second is the right.
+    /// The synth has no inputs, so only the outputs are walked.
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        self.update_tempo();
+        self.apply_editor_changes();
+        let samples = buffer.samples();
+        self.render(samples);
+        self.meter.update(&self.block, self.sample_rate);
+
+        let (_, outputs) = buffer.split();
+        for (channel, output_buffer) in outputs.into_iter().enumerate() {
+            for (output_sample, value) in output_buffer.iter_mut().zip(self.block.iter()) {
+                *output_sample = value[channel.min(1)];
+            }
+        }
+    }
+
+    /// Same as process for hosts running in 64 bit mode. The synth itself runs
+    /// in 32 bit, so the samples are just widened on the way out.
+    fn process_f64(&mut self, buffer: &mut AudioBuffer<f64>) {
+        self.update_tempo();
+        self.apply_editor_changes();
+        let samples = buffer.samples();
+        self.render(samples);
+        self.meter.update(&self.block, self.sample_rate);
+
+        let (_, outputs) = buffer.split();
+        for (channel, output_buffer) in outputs.into_iter().enumerate() {
+            for (output_sample, value) in output_buffer.iter_mut().zip(self.block.iter()) {
+                *output_sample = value[channel.min(1)] as f64;
+            }
         }
-
-        self.time += samples as f64 * sample;
     }
 }
 
 plugin_main!(Crust);
 
 #[test]
-fn test_sine_wave() {
-    assert_eq!(create_sine_wave(0, 0.0, 0.0, 0.0), 0.0);
-    assert_eq!(create_sine_wave(69, 0.0, 0.0, 0.0), 0.0);
-    assert_eq!(create_sine_wave(69, 1.0, 0.0005682, 0.0), 1.0);
-    assert_eq!(create_sine_wave(69, 1.0, 0.0017045, 0.0), -1.0);
+fn test_sine_wave() {
+    assert_eq!(create_sine_wave(0, 0.0, 0.0, 0.0), 0.0);
+    assert_eq!(create_sine_wave(69, 0.0, 0.0, 0.0), 0.0);
+    assert_eq!(create_sine_wave(69, 1.0, 0.0005682, 0.0), 1.0);
+    assert_eq!(create_sine_wave(69, 1.0, 0.0017045, 0.0), -1.0);
+}
+
+#[test]
+fn test_sawtooth_wave() {
+    assert_eq!(create_sawtooth_wave(0, 0.0, 0.0, 0.0), 0.0);
+    assert_eq!(create_sawtooth_wave(69, 0.0, 0.0, 0.0), 0.0);
+    assert_eq!(create_sawtooth_wave(69, 1.0, 0.00454545454, 0.0), 0.5);
+    assert_eq!(create_sawtooth_wave(69, 1.0, 0.00454545455, 0.0), -0.5);
+}
+
+/// Energy of a single DFT bin of the wave.
+#[cfg(test)]
+fn bin_energy(wave: &[f32], bin: usize) -> f64 {
+    let n = wave.len();
+    let (mut re, mut im) = (0.0, 0.0);
+
+    for (i, x) in wave.iter().enumerate() {
+        let angle = 2.0 * PI * (bin * i) as f64 / n as f64;
+        re += *x as f64 * angle.cos();
+        im -= *x as f64 * angle.sin();
+    }
+
+    re * re + im * im
+}
+
+/// Sums the energy of every DFT bin that is not a harmonic of the fundamental,
+/// which for a periodic wave is all aliasing.
+#[cfg(test)]
+fn inharmonic_energy(wave: &[f32], fundamental_bin: usize) -> f64 {
+    (1..wave.len() / 2).filter(|bin| bin % fundamental_bin != 0).map(|bin| bin_energy(wave, bin)).sum()
+}
+
+#[cfg(test)]
+use vst::host::HostBuffer;
+
+#[test]
+fn test_wave_from_phase() {
+    assert_eq!(create_sine_wave_from_phase(0.0, 1.0), 0.0);
+    assert_eq!(create_sine_wave_from_phase(0.25, 1.0), 1.0);
+    assert_eq!(create_sine_wave_from_phase(0.75, 1.0), -1.0);
+    assert_eq!(create_triangle_wave_from_phase(0.0, 1.0), 1.0);
+    assert_eq!(create_triangle_wave_from_phase(0.25, 1.0), 0.0);
+    assert_eq!(create_triangle_wave_from_phase(0.5, 1.0), -1.0);
+}
+
+#[test]
+fn test_advance_phase() {
+    assert_eq!(advance_phase(0.25, 0.5), 0.75);
+    assert_eq!(advance_phase(0.75, 0.5), 0.25);
+}
+
+#[test]
+fn test_detune_change_keeps_phase() {
+    let mut crust = Crust::default();
+    crust.process_midi_data([144, 69, 127]);
+    crust.generate_samples(50);
+    let phase = crust.notes[0].phases[0][0];
+
+    crust.set_parameter(2, 0.75);
+    assert_eq!(crust.notes[0].phases[0][0], phase);
+    crust.generate_samples(1);
+    let dt = midi_note_num_to_freq(69, crust.oscillators[0].detune.current, &Tuning::default()) / 44100.0;
+    assert!((crust.notes[0].phases[0][0] - (phase + dt)).abs() < 1e-9);
+}
+
+#[test]
+fn test_sawtooth_wave_blep() {
+    assert_eq!(create_sawtooth_wave_blep(0.0, 0.01, 0.0), 0.0);
+    assert_eq!(create_sawtooth_wave_blep(0.5, 0.01, 1.0), 0.0);
+    assert_eq!(create_sawtooth_wave_blep(0.75, 0.01, 1.0), 0.25);
+
+    // 2630 Hz completes exactly 263 cycles in 4410 samples, so every harmonic
+    // lands on a multiple of bin 263.
+    let naive: Vec<f32> = (0..4410).map(|i| (2630.0 * i as f64 / 44100.0 % 1.0 - 0.5) as f32).collect();
+    let blep: Vec<f32> = (0..4410).map(|i| create_sawtooth_wave_blep(2630.0 * i as f64 / 44100.0 % 1.0, 2630.0 / 44100.0, 1.0)).collect();
+    assert!(inharmonic_energy(&blep, 263) < inharmonic_energy(&naive, 263) / 2.0);
+}
+
+#[test]
+fn test_square_wave() {
+    assert_eq!(create_square_wave(0, 0.0, 0.0, 0.0), 0.0);
+    assert_eq!(create_square_wave(69, 0.0, 0.0, 0.0), 0.0);
+    assert_eq!(create_square_wave(69, 1.0, 0.0005682, 0.0), 0.4);
+    assert_eq!(create_square_wave(69, 1.0, 0.0017045, 0.0), -0.4);
+}
+
+#[test]
+fn test_square_wave_blep() {
+    assert_eq!(create_square_wave_blep(0.25, 0.01, 1.0, 0.5, SQUARE_BALANCE), 0.4);
+    assert_eq!(create_square_wave_blep(0.75, 0.01, 1.0, 0.5, SQUARE_BALANCE), -0.4);
+    assert_eq!(create_square_wave_blep(0.25, 0.01, 0.0, 0.5, SQUARE_BALANCE), 0.0);
+}
+
+#[test]
+fn test_square_level() {
+    let wavetable = Wavetable::default();
+    let mut oscillator = Oscillator { wave_index: 2, ..Default::default() };
+    assert_eq!(create_wave(&oscillator, &wavetable, 0.25, 0.01, 1.0), 0.4);
+    assert_eq!(create_wave(&oscillator, &wavetable, 0.75, 0.01, 1.0), -0.4);
+
+    oscillator.full_scale_square = 1.0;
+    assert_eq!(create_wave(&oscillator, &wavetable, 0.25, 0.01, 1.0), 1.0);
+    assert_eq!(create_wave(&oscillator, &wavetable, 0.75, 0.01, 1.0), -1.0);
+
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_parameter_text(75), "Balanced");
+    crust.set_parameter(75, 1.0);
+    assert_eq!(crust.get_parameter_text(75), "Full scale");
+    assert_eq!(crust.oscillators[0].square_level(), 1.0);
+}
+
+/// Fraction of one cycle that the pulse wave spends above zero.
+#[cfg(test)]
+fn duty_cycle(pulse_width: f32) -> f32 {
+    let dt = 0.001;
+    let high = (0..1000).filter(|i| create_square_wave_blep(*i as f64 * dt, dt, 1.0, pulse_width, SQUARE_BALANCE) > 0.0).count();
+
+    high as f32 / 1000.0
+}
+
+#[test]
+fn test_square_wave_blep_pulse_width() {
+    assert!((duty_cycle(0.5) - 0.5).abs() < 0.005);
+    assert!((duty_cycle(0.25) - 0.25).abs() < 0.005);
+    assert!((duty_cycle(0.75) - 0.75).abs() < 0.005);
+    assert!((duty_cycle(0.0) - 0.05).abs() < 0.005);
+}
+
+#[test]
+fn test_triangle_wave() {
+    assert_eq!(create_triangle_wave(0, 0.0, 0.0, 0.0), 0.0);
+    assert_eq!(create_triangle_wave(69, 0.0, 0.0, 0.0), 0.0);
+
+    // The triangle starts at its peak, crosses zero at a quarter and three
+    // quarters of the way through its cycle, and bottoms out halfway.
+    let period = 1.0 / 440.0;
+    let points = [(0.0, 1.0), (0.25, 0.0), (0.5, -1.0), (0.75, 0.0)];
+    for &(fraction, expected) in points.iter() {
+        assert!((create_triangle_wave(69, 1.0, fraction * period, 0.0) - expected).abs() < 1e-4);
+        assert_eq!(create_triangle_wave_from_phase(fraction, 1.0), expected);
+    }
+    assert_eq!(create_triangle_wave_from_phase(0.125, 0.5), 0.25);
+}
+
+#[test]
+fn test_midi_note_num_to_freq() {
+    assert_eq!(midi_note_num_to_freq(21, 0.0, &Tuning::default()), 27.5);
+    assert_eq!(midi_note_num_to_freq(33, 0.0, &Tuning::default()), 55.0);
+    assert_eq!(midi_note_num_to_freq(45, 0.0, &Tuning::default()), 110.0);
+    assert_eq!(midi_note_num_to_freq(57, 0.0, &Tuning::default()), 220.0);
+    assert_eq!(midi_note_num_to_freq(69, 0.0, &Tuning::default()), 440.0);
+    assert_eq!(midi_note_num_to_freq(81, 0.0, &Tuning::default()), 880.0);
+    assert_eq!(midi_note_num_to_freq(93, 0.0, &Tuning::default()), 1760.0);
+    assert_eq!(midi_note_num_to_freq(105, 0.0, &Tuning::default()), 3520.0);
+}
+
+#[test]
+fn test_detune_in_cents() {
+    for note in 0..128 {
+        assert_eq!(midi_note_num_to_freq(note, 1200.0, &Tuning::default()), midi_note_num_to_freq(note, 0.0, &Tuning::default()) * 2.0);
+        assert_eq!(midi_note_num_to_freq(note, -1200.0, &Tuning::default()), midi_note_num_to_freq(note, 0.0, &Tuning::default()) / 2.0);
+    }
+    assert!((midi_note_num_to_freq(69, 100.0, &Tuning::default()) - 466.1638).abs() < 1e-4);
+}
+
+#[test]
+fn test_detune_parameter() {
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_parameter(2), 0.5);
+    assert_eq!(crust.get_parameter_text(2), "+0 cents");
+
+    crust.set_parameter(2, 0.535);
+    assert_eq!(crust.get_parameter_text(2), "+7 cents");
+    crust.set_parameter(5, 0.0);
+    assert_eq!(crust.oscillators[1].detune.target, -100.0);
+    assert_eq!(crust.get_parameter_text(5), "-100 cents");
+}
+
+#[test]
+fn test_distortion() {
+    assert_eq!(distortion(0.75, 0.0, 1.0), 0.75);
+    assert_eq!(distortion(0.75, 0.32, 1.0), -12.776747);
+    assert_eq!(distortion(0.75, 0.50, 1.0), -20.385542);
+    assert_eq!(distortion(0.75, 0.75, 1.0), -30.953312);
+    assert_eq!(distortion(0.75, 1.0, 1.0), -41.521084);
+    assert_eq!(distortion(0.0, 1.0, 1.0), 0.0);
+    assert_eq!(distortion(-0.0, 0.5, 1.0), 0.0);
+}
+
+#[test]
+fn test_overdrive() {
+    assert_eq!(overdrive(0.0), 0.0);
+    assert_eq!(overdrive(0.32), 0.64);
+    assert_eq!(overdrive(0.50), 0.9166667);
+    assert_eq!(overdrive(0.75), 1.0);
+    assert_eq!(overdrive(1.0), 1.0);
+    assert_eq!(overdrive(2.0), 1.0);
+    assert_eq!(overdrive(f32::NAN), 0.0);
+}
+
+#[test]
+fn test_overdrive_is_continuous() {
+    for &breakpoint in [1.0 / 3.0, 2.0 / 3.0_f32].iter() {
+        let below = overdrive(breakpoint - 1e-4);
+        let above = overdrive(breakpoint + 1e-4);
+        assert!((above - below).abs() < 1e-3);
+    }
+    for i in 0..300 {
+        let input = i as f32 / 100.0 - 1.5;
+        assert!(overdrive(input + 0.01) >= overdrive(input));
+    }
+}
+
+#[test]
+fn test_overdrive_negative_input() {
+    assert_eq!(overdrive(-0.32), -0.64);
+    assert_eq!(overdrive(-0.50), -0.9166667);
+    assert_eq!(overdrive(-0.75), -1.0);
+    assert_eq!(overdrive(-1.0), -1.0);
+    assert_eq!(overdrive(-2.0), -1.0);
+    for i in 0..100 {
+        let input = i as f32 / 100.0;
+        assert_eq!(overdrive(-input), -overdrive(input));
+    }
+}
+
+#[test]
+fn test_note_off_releases_only_its_voice() {
+    let mut crust = Crust::default();
+    crust.process_midi_data([144, 60, 100]);
+    crust.process_midi_data([144, 64, 100]);
+    crust.process_midi_data([144, 67, 100]);
+    crust.process_midi_data([128, 64, 0]);
+
+    assert_eq!(crust.notes.len(), 3);
+    assert!(crust.notes[0].note_on);
+    assert!(!crust.notes[1].note_on);
+    assert!(crust.notes[2].note_on);
+}
+
+#[test]
+fn test_voice_is_finished_after_release() {
+    let envelope = Envelope::default();
+    let mut voice = Voice::new(69, 100);
+    assert!(!voice.is_finished(&envelope));
+
+    voice.note_on = false;
+    assert!(!voice.is_finished(&envelope));
+
+    voice.end_time = envelope.release as f64;
+    assert!(voice.is_finished(&envelope));
+}
+
+#[test]
+fn test_set_sample_rate() {
+    let mut crust = Crust::default();
+    crust.set_sample_rate(48000.0);
+    assert_eq!(crust.sample_rate, 48000.0);
+
+    crust.process_midi_data([144, 69, 100]);
+    crust.generate_samples(48);
+    assert!((crust.notes[0].duration - 0.001).abs() < 1e-12);
+}
+
+#[test]
+fn test_velocity_to_gain() {
+    assert_eq!(velocity_to_gain(0), 0.0);
+    assert_eq!(velocity_to_gain(127), 1.0);
+}
+
+#[test]
+fn test_velocity_scales_output() {
+    let mut soft = Crust::default();
+    let mut hard = Crust::default();
+    soft.process_midi_data([144, 69, 64]);
+    hard.process_midi_data([144, 69, 127]);
+
+    let soft_output = soft.generate_samples(100);
+    let hard_output = hard.generate_samples(100);
+
+    assert!(hard_output[99][0].abs() > soft_output[99][0].abs());
+    assert!((soft_output[99][0] / hard_output[99][0] - 64.0 / 127.0).abs() < 1e-5);
+}
+
+#[test]
+fn test_pitch_bend_to_offset() {
+    assert_eq!(pitch_bend_to_offset(0, 64), 0.0);
+    assert_eq!(pitch_bend_to_offset(127, 127), 1.0);
+    assert_eq!(pitch_bend_to_offset(0, 0), -1.0);
+}
+
+#[test]
+fn test_pitch_bend() {
+    let mut crust = Crust::default();
+    crust.process_midi_data([224, 127, 127]);
+    assert!((bend_freq(midi_note_num_to_freq(69, 0.0, &Tuning::default()), crust.bend_semitones()) - 493.88).abs() < 0.01);
+
+    crust.process_midi_data([224, 0, 64]);
+    assert_eq!(bend_freq(midi_note_num_to_freq(69, 0.0, &Tuning::default()), crust.bend_semitones()), 440.0);
+}
+
+/// Peak output of the filter for a sine at the given frequency once it has settled.
+#[cfg(test)]
+fn filter_peak(filter: &mut Filter, freq: f64) -> f32 {
+    let mut peak: f32 = 0.0;
+    for i in 0..44100 {
+        let output = filter.process(create_sine_wave_from_phase(freq * i as f64 / 44100.0 % 1.0, 1.0), 0, 44100.0);
+        if i > 22050 {
+            peak = peak.max(output.abs());
+        }
+    }
+
+    peak
+}
+
+#[test]
+fn test_filter_cutoff_freq() {
+    let mut filter = Filter::default();
+    assert_eq!(filter.cutoff_freq(), 20000.0);
+    filter.cutoff = 0.0;
+    assert_eq!(filter.cutoff_freq(), 20.0);
+    filter.cutoff = 0.5;
+    assert!((filter.cutoff_freq() - 632.46).abs() < 0.01);
+}
+
+#[test]
+fn test_low_pass_filter() {
+    let mut filter = Filter::default();
+    filter.cutoff = 0.5;
+    assert!(filter_peak(&mut filter, 100.0) > 0.9);
+    assert!(filter_peak(&mut filter, 8000.0) < 0.01);
+}
+
+#[test]
+fn test_low_pass_filter_resonance() {
+    let mut filter = Filter::default();
+    filter.cutoff = 0.5;
+    filter.resonance = 1.0;
+    let cutoff = filter.cutoff_freq() as f64;
+    let peak = filter_peak(&mut filter, cutoff);
+    assert!(peak > 2.0);
+    assert!(peak.is_finite());
+}
+
+#[test]
+fn test_envelope_curve() {
+    assert_eq!(envelope_curve(0.0, 0.0), 0.0);
+    assert_eq!(envelope_curve(0.25, 0.0), 0.25);
+    assert_eq!(envelope_curve(0.5, 0.0), 0.5);
+    assert_eq!(envelope_curve(1.0, 0.0), 1.0);
+    assert_eq!(envelope_curve(0.0, 1.0), 0.0);
+    assert_eq!(envelope_curve(1.0, 1.0), 1.0);
+    assert!(envelope_curve(0.5, 1.0) > 0.9);
+    assert!(envelope_curve(0.5, 0.5) > 0.5);
+}
+
+#[test]
+fn test_linear_envelope() {
+    let envelope = Envelope::default();
+    let mut voice = Voice::new(69, 127);
+
+    voice.duration = 0.025;
+    assert_eq!(get_amplitude(&envelope, &voice, 1.0), 0.025 / 0.05);
+    voice.duration = 0.075;
+    assert_eq!(get_amplitude(&envelope, &voice, 1.0), ((0.075 - 0.05) / 0.05) * (0.16 - 1.0) + 1.0);
+    voice.duration = 1.0;
+    assert_eq!(get_amplitude(&envelope, &voice, 1.0), 0.16);
+
+    voice.note_on = false;
+    voice.end_time = 0.07;
+    assert_eq!(generate_release(&envelope, &voice, 0.16), (0.07 / 0.14) * (0.0 - 0.16) + 0.16);
+}
+
+#[test]
+fn test_lfo_rate_hz() {
+    let mut lfo = Lfo::default();
+    lfo.rate = 0.0;
+    assert_eq!(lfo.rate_hz(), 0.1);
+    lfo.rate = 1.0;
+    assert!((lfo.rate_hz() - 20.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_lfo_pitch_modulation() {
+    let mut lfo = Lfo::default();
+    lfo.depth = 0.5;
+    lfo.rate = 1.0;
+
+    let offsets: Vec<f32> = (0..2205).map(|_| {
+        let offset = lfo.pitch_offset();
+        lfo.advance(1.0 / 44100.0, DEFAULT_TEMPO);
+        offset
+    }).collect();
+    let highest = offsets.iter().cloned().fold(0.0, f32::max);
+    let lowest = offsets.iter().cloned().fold(0.0, f32::min);
+    let mean = offsets.iter().sum::<f32>() / offsets.len() as f32;
+
+    assert!((highest - 1.0).abs() < 1e-3);
+    assert!((lowest + 1.0).abs() < 1e-3);
+    assert!(mean.abs() < 1e-3);
+    assert!(bend_freq(440.0, highest) > 440.0);
+    assert!(bend_freq(440.0, lowest) < 440.0);
+    assert_eq!(lfo.amplitude(), 1.0);
+}
+
+#[test]
+fn test_lfo_amplitude_modulation() {
+    let mut lfo = Lfo::default();
+    lfo.depth = 0.5;
+    lfo.destination = 1.0;
+
+    assert_eq!(lfo.pitch_offset(), 0.0);
+    lfo.phase = 0.25;
+    assert_eq!(lfo.amplitude(), 1.0);
+    lfo.phase = 0.75;
+    assert_eq!(lfo.amplitude(), 0.5);
+}
+
+#[test]
+fn test_sustain_pedal() {
+    let mut crust = Crust::default();
+    crust.process_midi_data([144, 60, 100]);
+    crust.process_midi_data([176, 64, 127]);
+    crust.process_midi_data([128, 60, 0]);
+    assert!(crust.notes[0].note_on);
+
+    crust.process_midi_data([176, 64, 0]);
+    assert!(!crust.notes[0].note_on);
+    assert!(crust.sustained_notes.is_empty());
+}
+
+#[test]
+fn test_sustain_pedal_replayed_note() {
+    let mut crust = Crust::default();
+    crust.process_midi_data([176, 64, 127]);
+    crust.process_midi_data([144, 60, 100]);
+    crust.process_midi_data([128, 60, 0]);
+    crust.process_midi_data([144, 60, 100]);
+    crust.process_midi_data([176, 64, 0]);
+
+    assert!(crust.notes.iter().all(|voice| voice.note_on));
+}
+
+#[test]
+fn test_voice_stealing() {
+    let mut crust = Crust::default();
+    for note in 40..60 {
+        crust.process_midi_data([144, note, 100]);
+    }
+    assert_eq!(crust.active_voices(), 16);
+    assert!(crust.notes[0..4].iter().all(|voice| voice.stolen && !voice.note_on));
+    assert!(crust.notes[4..].iter().all(|voice| voice.note_on));
+
+    crust.generate_samples(441);
+    assert_eq!(crust.notes.len(), 16);
+    assert_eq!(crust.notes[0].midi_note, 44);
+}
+
+#[test]
+fn test_polyphony_parameter() {
+    let mut crust = Crust::default();
+    crust.set_parameter(21, 0.0);
+    assert_eq!(crust.max_voices, 1);
+    crust.set_parameter(21, 1.0);
+    assert_eq!(crust.max_voices, 32);
+    crust.set_parameter(21, 15.0 / 31.0);
+    assert_eq!(crust.max_voices, 16);
+}
+
+#[test]
+fn test_glide() {
+    let mut crust = Crust::default();
+    crust.max_voices = 1;
+    crust.glide_time = 0.1;
+    crust.process_midi_data([144, 57, 100]);
+    assert_eq!(crust.current_freq, 220.0);
+
+    crust.process_midi_data([144, 69, 100]);
+    assert_eq!(crust.target_freq, 440.0);
+    crust.generate_samples(2205);
+    assert!((crust.current_freq - 311.127).abs() < 0.01);
+    assert!((crust.glide_offset() + 6.0).abs() < 1e-4);
+
+    crust.generate_samples(2206);
+    assert_eq!(crust.current_freq, 440.0);
+    assert_eq!(crust.glide_offset(), 0.0);
+}
+
+#[test]
+fn test_no_glide() {
+    let mut crust = Crust::default();
+    crust.max_voices = 1;
+    crust.process_midi_data([144, 57, 100]);
+    crust.process_midi_data([144, 69, 100]);
+    assert_eq!(crust.current_freq, 440.0);
+
+    crust.max_voices = 16;
+    crust.glide_time = 0.1;
+    crust.process_midi_data([144, 57, 100]);
+    assert_eq!(crust.current_freq, 220.0);
+}
+
+#[test]
+fn test_pan_gains() {
+    assert_eq!(pan_gains(0.0), [1.0, 0.0]);
+    assert!((pan_gains(0.5)[0] - 0.5_f32.sqrt()).abs() < 1e-6);
+    assert!((pan_gains(0.5)[1] - 0.5_f32.sqrt()).abs() < 1e-6);
+    assert!(pan_gains(1.0)[0].abs() < 1e-6);
+    assert_eq!(pan_gains(1.0)[1], 1.0);
+}
+
+#[test]
+fn test_oscillator_pan() {
+    let mut crust = Crust::default();
+    crust.oscillators[0].pan = 0.0;
+    crust.oscillators[1].volume = SmoothedParam::new(0.0);
+    crust.process_midi_data([144, 69, 127]);
+
+    let output = crust.generate_samples(441);
+    assert!(output.iter().all(|sample| sample[1] == 0.0));
+    assert!(output.iter().any(|sample| sample[0] != 0.0));
+}
+
+#[test]
+fn test_sub_freq() {
+    assert_eq!(sub_freq(69, &Tuning::default()), 220.0);
+    assert_eq!(sub_freq(81, &Tuning::default()), 440.0);
+    assert_eq!(sub_freq(9, &Tuning::default()), 6.875);
+    for note in 0..128 {
+        assert!((sub_freq(note, &Tuning::default()) / midi_note_num_to_freq(note, 0.0, &Tuning::default()) - 0.5).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_sub_oscillator() {
+    let mut crust = Crust { sub_level: 1.0, ..Default::default() };
+    crust.oscillators[0].volume = SmoothedParam::new(0.0);
+    crust.oscillators[1].volume = SmoothedParam::new(0.0);
+    crust.process_midi_data([144, 69, 127]);
+    crust.generate_samples(100);
+    assert!((crust.notes[0].sub_phase - 100.0 * 220.0 / 44100.0).abs() < 1e-9);
+
+    let output = crust.generate_samples(100);
+    assert!(output.iter().any(|sample| sample[0] != 0.0));
+}
+
+#[test]
+fn test_smoothed_param() {
+    let mut param = SmoothedParam::new(0.0);
+    param.target = 1.0;
+    let coefficient = smoothing_coefficient(44100.0);
+
+    let first = param.next(coefficient);
+    assert!(first > 0.0 && first < 0.01);
+    for _ in 0..220 {
+        param.next(coefficient);
+    }
+    assert!((param.current - (1.0 - (-1.0_f32).exp())).abs() < 0.01);
+    for _ in 0..2205 {
+        param.next(coefficient);
+    }
+    assert!((param.current - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_volume_is_smoothed() {
+    let mut crust = Crust::default();
+    crust.set_parameter(1, 1.0);
+    assert_eq!(crust.get_parameter(1), 1.0);
+    assert_eq!(crust.oscillators[0].volume.current, 0.5);
+
+    crust.generate_samples(4410);
+    assert!((crust.oscillators[0].volume.current - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_fm_phase() {
+    assert_eq!(fm_phase(0.25, 1.0, 0.0), 0.25);
+    assert!((fm_phase(0.25, 1.0, PI as f32) - 0.75).abs() < 1e-6);
+    assert!((fm_phase(0.25, -1.0, PI as f32) - 0.75).abs() < 1e-6);
+}
+
+#[test]
+fn test_zero_fm_amount() {
+    let mut plain = Crust::default();
+    let mut fm = Crust { fm_routing: 1.0, ..Default::default() };
+    plain.process_midi_data([144, 69, 127]);
+    fm.process_midi_data([144, 69, 127]);
+    assert_eq!(plain.generate_samples(441), fm.generate_samples(441));
+
+    fm.fm_amount = 0.5;
+    assert!(plain.generate_samples(441) != fm.generate_samples(441));
+}
+
+#[test]
+fn test_ring_modulate() {
+    assert_eq!(ring_modulate(0.5, 0.5), 0.25);
+    assert_eq!(ring_modulate(0.5, -1.0), -0.5);
+
+    // 100 cycles of each sine in 4410 samples.
+    let wave: Vec<f32> = (0..4410).map(|i| {
+        let phase = 100.0 * i as f64 / 4410.0 % 1.0;
+        ring_modulate(create_sine_wave_from_phase(phase, 1.0), create_sine_wave_from_phase(phase, 1.0))
+    }).collect();
+    assert!(bin_energy(&wave, 200) > 1000.0 * bin_energy(&wave, 100));
+}
+
+#[test]
+fn test_amplitude_modulate() {
+    assert_eq!(amplitude_modulate(0.5, 0.0), 0.5);
+    assert_eq!(amplitude_modulate(0.5, 1.0), 1.0);
+    assert_eq!(amplitude_modulate(0.5, -1.0), 0.0);
+}
+
+#[test]
+fn test_unison_offset() {
+    assert_eq!(unison_offset(0, 1), 0.0);
+    assert_eq!(unison_offset(0, 3), -1.0);
+    assert_eq!(unison_offset(1, 3), 0.0);
+    assert_eq!(unison_offset(2, 3), 1.0);
+    assert_eq!(unison_offset(1, 5), -0.5);
+}
+
+#[test]
+fn test_single_unison_voice() {
+    let mut plain = Crust::default();
+    let mut unison = Crust { unison_spread: 1.0, ..Default::default() };
+    plain.process_midi_data([144, 69, 127]);
+    unison.process_midi_data([144, 69, 127]);
+    assert_eq!(plain.generate_samples(441), unison.generate_samples(441));
+
+    unison.unison_count = 3;
+    assert!(plain.generate_samples(441) != unison.generate_samples(441));
+}
+
+#[test]
+fn test_unison_parameter() {
+    let mut crust = Crust::default();
+    crust.set_parameter(30, 1.0);
+    assert_eq!(crust.unison_count, 7);
+    crust.set_parameter(30, 0.0);
+    assert_eq!(crust.unison_count, 1);
+}
+
+#[test]
+fn test_saturate() {
+    let mut crust = Crust::default();
+    assert_eq!(crust.saturate(0.75), 0.75);
+
+    crust.distortion = 1.0;
+    assert_eq!(crust.saturate(0.0), 0.0);
+    assert_eq!(crust.saturate(0.75), -1.0);
+    assert!(crust.saturate(0.01).abs() <= 1.0);
+    assert!(crust.saturate(-0.75).abs() <= 1.0);
+}
+
+#[test]
+fn test_distortion_output_range() {
+    let mut crust = Crust { distortion: 0.5, ..Default::default() };
+    crust.oscillators[0].volume = SmoothedParam::new(1.0);
+    crust.oscillators[1].volume = SmoothedParam::new(1.0);
+    crust.process_midi_data([144, 69, 127]);
+
+    let output = crust.generate_samples(4410);
+    assert!(output.iter().all(|sample| sample[0].is_finite() && sample[0].abs() <= 1.0));
+}
+
+#[test]
+fn test_overdrive_stage() {
+    let mut crust = Crust { saturation_type: 1.0, ..Default::default() };
+    crust.distortion = 1.0;
+    assert_eq!(crust.saturate(0.2), 0.2);
+
+    crust.drive = 1.0 / 9.0;
+    assert_eq!(crust.saturate(0.1), overdrive(0.2));
+    assert_eq!(crust.saturate(-0.1), overdrive(-0.2));
+    crust.drive = 1.0;
+    assert_eq!(crust.saturate(0.5), 1.0);
+    assert_eq!(crust.saturate(-0.5), -1.0);
+}
+
+#[test]
+fn test_high_pass_filter() {
+    let mut filter = Filter { cutoff: 0.0, filter_type: 0.5, ..Default::default() };
+    assert_eq!(filter.type_name(), "High-pass");
+    assert!(filter_peak(&mut filter, 5000.0) > 0.99);
+    filter.cutoff = 0.5;
+    assert!(filter_peak(&mut filter, 20.0) < 0.01);
+}
+
+#[test]
+fn test_band_pass_filter() {
+    let mut filter = Filter { cutoff: 0.5, filter_type: 1.0, ..Default::default() };
+    assert_eq!(filter.type_name(), "Band-pass");
+    let cutoff = filter.cutoff_freq() as f64;
+    let centre = filter_peak(&mut filter, cutoff);
+    assert!((centre - 0.5).abs() < 0.01);
+    assert!(filter_peak(&mut filter, 20.0) < centre * 0.1);
+    assert!(filter_peak(&mut filter, 15000.0) < centre * 0.1);
+}
+
+#[test]
+fn test_midi_channel_filter() {
+    let mut crust = Crust { midi_channel: 1.0 / 16.0, ..Default::default() };
+    assert!(crust.listens_to(0));
+    crust.process_midi_data([0x92, 60, 100]);
+    assert!(crust.notes.is_empty());
+
+    crust.process_midi_data([0x90, 60, 100]);
+    assert_eq!(crust.notes.len(), 1);
+
+    crust.midi_channel = 0.0;
+    crust.process_midi_data([0x92, 64, 100]);
+    assert_eq!(crust.notes.len(), 2);
+}
+
+#[test]
+fn test_note_on_off_any_channel() {
+    let mut crust = Crust::default();
+    for channel in [0u8, 1, 9, 15].iter() {
+        crust.process_midi_data([0x90 | channel, 60 + channel, 100]);
+    }
+    assert_eq!(crust.notes.len(), 4);
+
+    crust.process_midi_data([0x81, 61, 0]);
+    assert!(crust.notes[0].note_on);
+    assert!(!crust.notes[1].note_on);
+
+    crust.process_midi_data([0xE9, 0, 127]);
+    assert!(crust.pitch_bend > 0.0);
+}
+
+#[test]
+fn test_note_on_zero_velocity_is_note_off() {
+    let mut crust = Crust::default();
+    crust.process_midi_data([144, 60, 100]);
+    crust.process_midi_data([144, 60, 0]);
+    assert_eq!(crust.notes.len(), 1);
+    assert!(!crust.notes[0].note_on);
+
+    let release = crust.notes[0].release_time(&crust.envelope);
+    crust.generate_samples((release as f64 * crust.sample_rate) as usize + 2);
+    assert!(crust.notes.is_empty());
+}
+
+#[test]
+fn test_preset_data_round_trip() {
+    let mut crust = Crust::default();
+    let count = crust.get_info().parameters;
+    for index in 0..count {
+        crust.set_parameter(index, (index as f32 * 0.37 + 0.1) % 1.0);
+    }
+    let saved: Vec<f32> = (0..count).map(|index| crust.get_parameter(index)).collect();
+    let data = crust.get_preset_data();
+
+    let mut loaded = Crust::default();
+    for index in 0..count {
+        loaded.set_parameter(index, 0.0);
+    }
+    loaded.load_preset_data(&data);
+    for index in 0..count {
+        assert!((loaded.get_parameter(index) - saved[index as usize]).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_load_older_preset_data() {
+    let mut crust = Crust::default();
+    crust.load_preset_data(&[1, 1, 0, 0, 0, 0, 0, 0, 0x3f]);
+    assert_eq!(crust.get_parameter_text(0), "Saw");
+    assert_eq!(crust.get_parameter(1), Crust::default().get_parameter(1));
+
+    crust.load_preset_data(&[PRESET_VERSION + 1, 1, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(crust.get_parameter_text(0), "Saw");
+}
+
+#[test]
+fn test_factory_presets() {
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_info().presets, 6);
+    assert_eq!(crust.get_preset_name(2), "Acid Bass");
+    assert_eq!(crust.get_preset_name(6), "");
+
+    crust.change_preset(2);
+    assert_eq!(crust.get_preset_num(), 2);
+    for &(index, value) in FACTORY_PRESETS[2].parameters {
+        assert!((crust.get_parameter(index) - value).abs() < 1e-6);
+    }
+    assert_eq!(crust.max_voices, 1);
+    assert_eq!(crust.oscillators[1].volume.target, 0.0);
+
+    crust.change_preset(0);
+    assert_eq!(crust.max_voices, 16);
+    assert_eq!(crust.oscillators[1].volume.target, Crust::default().oscillators[1].volume.target);
+}
+
+#[test]
+fn test_waveform_steps() {
+    let mut crust = Crust::default();
+    let positions = [(0.0, "Sine"), (0.19, "Sine"), (0.3, "Saw"), (0.5, "Square"), (0.59, "Square"),
+                     (0.7, "Triangle"), (0.8, "Wavetable"), (1.0, "Wavetable")];
+    for &(position, name) in positions.iter() {
+        crust.set_parameter(0, position);
+        assert_eq!(crust.get_parameter_text(0), name);
+    }
+
+    crust.set_parameter(0, 0.3);
+    assert_eq!(crust.get_parameter(0), 0.25);
+    crust.set_parameter(0, crust.get_parameter(0));
+    assert_eq!(crust.oscillators[0].wave_index, 1);
+    assert!(crust.can_be_automated(0));
+    assert!(!crust.can_be_automated(crust.get_info().parameters));
+}
+
+#[test]
+fn test_filter_envelope_sweeps_cutoff() {
+    let mut crust = Crust {
+        filter: Filter { cutoff: 0.2, ..Default::default() },
+        filter_envelope: Envelope { attack: 0.1, decay: 0.1, sustain: 0.5, release: 0.1, ..Default::default() },
+        filter_env_amount: 0.5,
+        ..Default::default()
+    };
+    crust.note_on(60, 100);
+
+    crust.generate_samples(2205);
+    assert!((crust.filter.cutoff_mod - 0.25).abs() < 0.01);
+    crust.generate_samples(2205);
+    assert!((crust.filter.cutoff_mod - 0.5).abs() < 0.01);
+    crust.generate_samples(4410);
+    assert!((crust.filter.cutoff_mod - 0.25).abs() < 0.01);
+    assert!(crust.filter.modulated_cutoff_freq() > crust.filter.cutoff_freq());
+
+    crust.note_off(60);
+    crust.generate_samples(4410);
+    assert!(crust.filter.cutoff_mod.abs() < 0.01);
+}
+
+#[test]
+fn test_master_tuning() {
+    let tuning = Tuning { reference: 432.0, ..Default::default() };
+    assert_eq!(midi_note_num_to_freq(69, 0.0, &tuning), 432.0);
+    assert_eq!(midi_note_num_to_freq(81, 0.0, &tuning), 864.0);
+    assert_eq!(midi_note_num_to_freq(69, 1200.0, &tuning), 864.0);
+    assert_eq!(sub_freq(69, &tuning), 216.0);
+
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_parameter_text(43), "440.0 Hz");
+    crust.set_parameter(43, 17.0 / 51.0);
+    assert!((crust.tuning.reference - 432.0).abs() < 1e-4);
+    crust.pitch_bend(127, 127);
+    let bent = bend_freq(midi_note_num_to_freq(69, 0.0, &crust.tuning), crust.bend_semitones());
+    assert!((bent / 432.0 - 2.0_f64.powf(2.0 / 12.0)).abs() < 1e-3);
+}
+
+#[test]
+fn test_pythagorean_tuning_table() {
+    let pythagorean = [0.0, 13.685, 3.91, -5.865, 7.82, -1.955, 11.73, 1.955, -7.82, 5.865, -3.91, 9.775];
+    let mut data = Crust::default().get_preset_data();
+    // The table sits before the empty controller mapping count.
+    let table_start = data.len() - 52;
+    data.truncate(table_start);
+    for cents in pythagorean.iter() {
+        data.extend_from_slice(&(*cents as f32).to_le_bytes());
+    }
+
+    let mut crust = Crust::default();
+    crust.load_preset_data(&data);
+    assert_eq!(crust.tuning.cents[7], 1.955);
+    let fifth = midi_note_num_to_freq(67, 0.0, &crust.tuning) / midi_note_num_to_freq(60, 0.0, &crust.tuning);
+    assert!((fifth - 1.5).abs() < 1e-5);
+    assert!((midi_note_num_to_freq(67, 0.0, &crust.tuning) - 392.438).abs() < 1e-3);
+
+    // Data without a table keeps the current tuning.
+    crust.load_preset_data(&data[..table_start]);
+    assert_eq!(crust.tuning.cents[7], 1.955);
+}
+
+#[test]
+fn test_sustain_is_fraction_of_peak() {
+    let envelope = Envelope { attack: 0.1, decay: 0.1, sustain: 0.5, release: 0.1, ..Default::default() };
+    let mut voice = Voice::new(69, 127);
+
+    voice.duration = 0.2;
+    assert_eq!(get_amplitude(&envelope, &voice, 1.0), 0.5);
+    voice.duration = 1.0;
+    assert_eq!(get_amplitude(&envelope, &voice, 1.0), 0.5);
+    assert_eq!(get_amplitude(&envelope, &voice, 0.5), 0.25);
+
+    // The decay never rises above the peak, even at full sustain.
+    let full = Envelope { sustain: 1.0, ..envelope };
+    let mut last = 0.0;
+    for step in 0..300 {
+        voice.duration = step as f64 * 0.001;
+        let level = get_amplitude(&full, &voice, 0.5);
+        assert!(level >= last && level <= 0.5 + 1e-6);
+        last = level;
+    }
+
+    voice.note_on = false;
+    voice.duration = 1.0;
+    voice.end_time = 0.0;
+    assert_eq!(generate_release(&envelope, &voice, 0.5), 0.5);
+}
+
+#[test]
+fn test_release_mid_attack_is_continuous() {
+    let mut crust = Crust {
+        envelope: Envelope {
+            attack: 0.1,
+            decay: 0.1,
+            sustain: 0.5,
+            release: 0.1,
+            attack_curve: 0.5,
+            decay_curve: 0.5,
+            release_curve: 0.5,
+        },
+        ..Default::default()
+    };
+    crust.note_on(69, 127);
+    crust.generate_samples(2205);
+    let held = crust.notes[0].release_level;
+    assert!(held > 0.0 && held < 1.0);
+
+    crust.note_off(69);
+    let voice = &crust.notes[0];
+    assert_eq!(generate_release(&crust.envelope, voice, voice.release_level), held);
+
+    crust.generate_samples(1);
+    let voice = &crust.notes[0];
+    let first_release = generate_release(&crust.envelope, voice, voice.release_level);
+    assert!(first_release < held && held - first_release < 0.01);
+}
+
+/// Ratio of low to high frequency energy in a block of noise of the given color.
+#[cfg(test)]
+fn noise_tilt(color: f32) -> f64 {
+    let mut generator = NoiseGenerator { color, ..Default::default() };
+    let wave: Vec<f32> = (0..4096).map(|_| generator.next(1.0)).collect();
+    let low: f64 = (1..17).map(|bin| bin_energy(&wave, bin)).sum();
+    let high: f64 = (1024..1040).map(|bin| bin_energy(&wave, bin)).sum();
+
+    low / high
+}
+
+#[test]
+fn test_noise_colors() {
+    assert_eq!(NoiseGenerator { color: 0.0, ..Default::default() }.color_name(), "White");
+    assert_eq!(NoiseGenerator { color: 0.5, ..Default::default() }.color_name(), "Pink");
+    assert_eq!(NoiseGenerator::default().color_name(), "Brown");
+
+    let white = noise_tilt(0.0);
+    let pink = noise_tilt(0.5);
+    let brown = noise_tilt(1.0);
+    assert!(white < 10.0);
+    assert!(pink > white * 4.0);
+    assert!(brown > pink * 4.0);
+}
+
+#[test]
+fn test_noise_silent_without_notes() {
+    let mut crust = Crust { noise: 1.0, ..Default::default() };
+    assert!(crust.generate_samples(1000).iter().all(|frame| frame[0] == 0.0 && frame[1] == 0.0));
+
+    crust.note_on(60, 127);
+    assert!(crust.generate_samples(1000).iter().any(|frame| frame[0] != 0.0));
+
+    crust.note_off(60);
+    crust.generate_samples(44100);
+    assert!(crust.notes.is_empty());
+    assert!(crust.generate_samples(1000).iter().all(|frame| frame[0].abs() < 1e-6));
+}
+
+#[test]
+fn test_delay_echo() {
+    let mut delay = Delay { time: 0.01, feedback: 0.5, mix: 1.0, ..Default::default() };
+    let mut output = vec![delay.process([1.0, -1.0], 44100.0, DEFAULT_TEMPO)];
+    for _ in 0..1000 {
+        output.push(delay.process([0.0, 0.0], 44100.0, DEFAULT_TEMPO));
+    }
+
+    assert_eq!(output[0], [0.0, 0.0]);
+    assert_eq!(output[441], [1.0, -1.0]);
+    assert_eq!(output[882], [0.5, -0.5]);
+    assert!(output.iter().enumerate().all(|(i, frame)| i == 441 || i == 882 || frame[0] == 0.0));
+
+    // Feedback at or above 1 is held just below it, so echoes still fade.
+    let mut runaway = Delay { time: 0.001, feedback: 1.5, mix: 1.0, ..Default::default() };
+    runaway.process([1.0, 1.0], 44100.0, DEFAULT_TEMPO);
+    let peak = (0..44100).map(|_| runaway.process([0.0, 0.0], 44100.0, DEFAULT_TEMPO)[0].abs()).fold(0.0, f32::max);
+    assert!(peak <= 1.0);
+    assert!(runaway.process([0.0, 0.0], 44100.0, DEFAULT_TEMPO)[0].abs() < 1e-6);
+}
+
+#[test]
+fn test_delay_resized_with_sample_rate() {
+    let mut crust = Crust::default();
+    assert_eq!(crust.delay.line.buffer.len(), 88201);
+    crust.set_sample_rate(96000.0);
+    assert_eq!(crust.delay.line.buffer.len(), 192001);
+}
+
+#[test]
+fn test_delay_line_interpolates() {
+    let mut line = DelayLine::new(0.001, 44100.0);
+    line.write([1.0, 0.0]);
+    line.write([0.0, 1.0]);
+    assert_eq!(line.read(1.0), [0.0, 1.0]);
+    assert_eq!(line.read(2.0), [1.0, 0.0]);
+    assert_eq!(line.read(1.5), [0.5, 0.5]);
+}
+
+#[test]
+fn test_chorus_tap_sweep() {
+    let mut chorus = Chorus { depth: 1.0, mix: 1.0, ..Default::default() };
+    let shortest = CHORUS_BASE_DELAY * 44100.0;
+    let longest = (CHORUS_BASE_DELAY + CHORUS_MAX_DEPTH) * 44100.0;
+    let mut low = longest;
+    let mut high = shortest;
+
+    for _ in 0..(44100.0 / chorus.rate_hz()) as usize {
+        let delay = chorus.tap_delay(0, 0, 44100.0);
+        assert!(delay >= shortest - 1e-9 && delay <= longest + 1e-9);
+        low = low.min(delay);
+        high = high.max(delay);
+        chorus.process([0.0, 0.0], 44100.0);
+    }
+    assert!(low - shortest < 0.01);
+    assert!(longest - high < 0.01);
+
+    chorus.depth = 0.0;
+    assert_eq!(chorus.tap_delay(1, 1, 44100.0), shortest);
+}
+
+#[test]
+fn test_bitcrusher_one_bit() {
+    let mut crusher = Bitcrusher { bit_depth: 0.0, ..Default::default() };
+    assert_eq!(crusher.bits(), 1);
+    let mut levels: Vec<f32> = Vec::new();
+    for i in 0..200 {
+        let output = crusher.process([create_sine_wave_from_phase(i as f64 / 200.0, 1.0); 2]);
+        if !levels.contains(&output[0]) {
+            levels.push(output[0]);
+        }
+    }
+    levels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(levels, vec![-0.5, 0.5]);
+
+    assert_eq!(Bitcrusher::default().quantize(0.123), 0.123);
+}
+
+#[test]
+fn test_bitcrusher_downsample() {
+    let mut crusher = Bitcrusher { downsample: 3.0 / 63.0, ..Default::default() };
+    assert_eq!(crusher.factor(), 4);
+    let output: Vec<f32> = (0..12).map(|i| crusher.process([i as f32 * 0.01; 2])[0]).collect();
+    for (i, value) in output.iter().enumerate() {
+        assert_eq!(*value, (i / 4 * 4) as f32 * 0.01);
+    }
+}
+
+#[test]
+fn test_reports_latency() {
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_info().initial_delay, 88);
+    assert_eq!(crust.get_info().initial_delay, crust.latency());
+
+    // The limiter's lookahead is reported whether it is on or off, and the
+    // host only reads it once, so it stays the same at any sample rate.
+    crust.limiter.enabled = 1.0;
+    assert_eq!(crust.get_info().initial_delay, 88);
+    crust.set_sample_rate(96000.0);
+    assert_eq!(crust.latency(), 88);
+    assert_eq!(crust.get_info().initial_delay, 88);
+}
+
+#[test]
+fn test_all_notes_off() {
+    let mut crust = Crust::default();
+    crust.process_midi_data([144, 60, 100]);
+    crust.process_midi_data([144, 64, 100]);
+    crust.process_midi_data([176, 64, 127]);
+    crust.process_midi_data([128, 64, 0]);
+
+    crust.process_midi_data([176, 123, 0]);
+    assert!(crust.notes.iter().all(|voice| !voice.note_on));
+    assert!(crust.sustained_notes.is_empty());
+    crust.generate_samples((crust.envelope.release as f64 * crust.sample_rate) as usize + 2);
+    assert!(crust.notes.is_empty());
+}
+
+#[test]
+fn test_all_sound_off() {
+    let mut crust = Crust::default();
+    crust.process_midi_data([144, 60, 100]);
+    crust.process_midi_data([144, 64, 100]);
+    crust.process_midi_data([176, 120, 0]);
+    assert!(crust.notes.is_empty());
+}
+
+#[test]
+fn test_legato_keeps_envelope_running() {
+    let mut crust = Crust { voice_mode: 1.0, ..Default::default() };
+    crust.note_on(60, 100);
+    crust.generate_samples(100);
+    let duration = crust.notes[0].duration;
+
+    crust.note_on(64, 100);
+    assert_eq!(crust.notes.len(), 1);
+    assert_eq!(crust.notes[0].midi_note, 64);
+    assert_eq!(crust.notes[0].duration, duration);
+    assert_eq!(crust.target_freq, midi_note_num_to_freq(64, 0.0, &crust.tuning));
+
+    crust.note_off(64);
+    assert!(crust.notes[0].note_on);
+    assert_eq!(crust.notes[0].midi_note, 60);
+    assert_eq!(crust.target_freq, midi_note_num_to_freq(60, 0.0, &crust.tuning));
+
+    crust.note_off(60);
+    assert!(!crust.notes[0].note_on);
+
+    // Once every key is up the next note starts a fresh envelope.
+    crust.note_on(67, 100);
+    assert_eq!(crust.notes.last().unwrap().duration, 0.0);
+    assert_eq!(crust.notes.iter().filter(|voice| voice.note_on).count(), 1);
+}
+
+#[test]
+fn test_lfo_retrigger() {
+    let mut crust = Crust::default();
+    crust.note_on(60, 100);
+    crust.generate_samples(1000);
+    let phase = crust.lfo.phase;
+    assert!(phase > 0.0);
+    crust.note_on(64, 100);
+    assert_eq!(crust.lfo.phase, phase);
+
+    crust.lfo.retrigger = 1.0;
+    crust.note_on(67, 100);
+    assert_eq!(crust.lfo.phase, 0.0);
+}
+
+#[test]
+fn test_velocity_opens_filter() {
+    let mut cutoffs = Vec::new();
+    for velocity in [1, 64, 127].iter() {
+        let mut crust = Crust {
+            filter: Filter { cutoff: 0.3, ..Default::default() },
+            velocity_to_cutoff: 0.5,
+            ..Default::default()
+        };
+        crust.note_on(60, *velocity);
+        crust.generate_samples(1);
+        cutoffs.push(crust.filter.modulated_cutoff_freq());
+    }
+
+    assert!(cutoffs[0] < cutoffs[1] && cutoffs[1] < cutoffs[2]);
+    assert!((cutoffs[2] - 20.0 * 1000.0_f32.powf(0.8)).abs() < 0.01);
+}
+
+#[test]
+fn test_filter_key_tracking() {
+    let mut cutoffs = Vec::new();
+    for note in [48, 60, 72].iter() {
+        let mut crust = Crust {
+            filter: Filter { cutoff: 0.5, ..Default::default() },
+            key_track: 1.0,
+            ..Default::default()
+        };
+        crust.note_on(*note, 100);
+        crust.generate_samples(1);
+        cutoffs.push(crust.filter.modulated_cutoff_freq());
+    }
+
+    assert!((cutoffs[1] - Filter { cutoff: 0.5, ..Default::default() }.cutoff_freq()).abs() < 1e-3);
+    assert!((cutoffs[2] / cutoffs[1] - 2.0).abs() < 1e-4);
+    assert!((cutoffs[1] / cutoffs[0] - 2.0).abs() < 1e-4);
+}
+
+/// The note of the voice that is currently held, if any.
+#[cfg(test)]
+fn held_note(crust: &Crust) -> Option<u8> {
+    crust.notes.iter().rev().find(|voice| voice.note_on).map(|voice| voice.midi_note)
+}
+
+#[test]
+fn test_arpeggiator_up() {
+    let mut crust = Crust {
+        arpeggiator: Arpeggiator { enabled: 1.0, rate: 1.0, ..Default::default() },
+        ..Default::default()
+    };
+    let step = (crust.sample_rate / crust.arpeggiator.rate_hz() as f64) as usize;
+
+    crust.note_on(60, 100);
+    crust.note_on(64, 100);
+    assert_eq!(held_note(&crust), Some(60));
+
+    // Check halfway through each step so the clock never lands on a boundary.
+    crust.generate_samples(step / 2);
+    assert_eq!(held_note(&crust), Some(60));
+    let mut played = Vec::new();
+    for _ in 0..4 {
+        crust.generate_samples(step);
+        played.push(held_note(&crust));
+    }
+    assert_eq!(played, vec![Some(64), Some(60), Some(64), Some(60)]);
+    assert_eq!(crust.notes.iter().filter(|voice| voice.note_on).count(), 1);
+
+    crust.note_off(60);
+    crust.note_off(64);
+    assert_eq!(held_note(&crust), None);
+}
+
+#[test]
+fn test_arpeggiator_patterns() {
+    let mut arpeggiator = Arpeggiator { keys: vec![67, 60, 64], octaves: 1.0 / 3.0, ..Default::default() };
+    assert_eq!(arpeggiator.pattern(), vec![60, 64, 67, 72, 76, 79]);
+
+    arpeggiator.octaves = 0.0;
+    arpeggiator.mode = 0.4;
+    let mut rng = noise_rng(0);
+    let down: Vec<u8> = (0..4).filter_map(|_| arpeggiator.next_note(&mut rng)).collect();
+    assert_eq!(down, vec![67, 64, 60, 67]);
+
+    arpeggiator.mode = 0.6;
+    arpeggiator.step = 0;
+    let up_down: Vec<u8> = (0..6).filter_map(|_| arpeggiator.next_note(&mut rng)).collect();
+    assert_eq!(up_down, vec![60, 64, 67, 64, 60, 64]);
+    assert_eq!(arpeggiator.mode_name(), "Up-down");
+}
+
+#[test]
+fn test_division_to_samples() {
+    assert_eq!(division_to_samples(1.0, 120.0, 44100.0), 22050.0);
+    assert_eq!(division_to_samples(0.5, 120.0, 44100.0), 11025.0);
+    assert_eq!(division_to_samples(1.0, 90.0, 48000.0), 32000.0);
+}
+
+#[test]
+fn test_tempo_sync() {
+    assert_eq!(sync_text(0.0), "Off");
+    assert_eq!(sync_text(1.0), "1/32");
+    let quarter = 4.0 / NOTE_DIVISIONS.len() as f32;
+    assert_eq!(sync_text(quarter), "1/4");
+
+    let lfo = Lfo { sync: quarter, ..Default::default() };
+    assert_eq!(lfo.frequency(DEFAULT_TEMPO), 2.0);
+    assert_eq!(lfo.frequency(60.0), 1.0);
+
+    let delay = Delay { sync: quarter, ..Default::default() };
+    assert_eq!(delay.delay_samples(44100.0, DEFAULT_TEMPO), 22050.0);
+    assert_eq!(Delay::default().delay_samples(44100.0, DEFAULT_TEMPO), 11025.0);
+
+    let mut crust = Crust::default();
+    assert!(crust.host.is_none());
+    crust.update_tempo();
+    assert_eq!(crust.tempo, DEFAULT_TEMPO);
+    assert!(Crust::new(HostCallback::default()).host.is_some());
+}
+
+#[test]
+fn test_soft_clip() {
+    assert_eq!(soft_clip(0.0), 0.0);
+    assert!((soft_clip(0.01) - 0.01).abs() < 1e-5);
+    assert!(soft_clip(5.0) <= 1.0 && soft_clip(5.0) > 0.99);
+    assert!(soft_clip(-5.0) >= -1.0 && soft_clip(-5.0) < -0.99);
+
+    let mut last = soft_clip(-5.0);
+    for step in 1..=1000 {
+        let output = soft_clip(-5.0 + step as f32 * 0.01);
+        assert!(output >= last && output.abs() <= 1.0);
+        last = output;
+    }
+}
+
+#[test]
+fn test_soft_clipping_switch() {
+    let mut crust = Crust { master_vol: 1.0, ..Default::default() };
+    crust.oscillators[0].volume = SmoothedParam::new(1.0);
+    crust.oscillators[1].volume = SmoothedParam::new(1.0);
+    for note in [60, 64, 67, 72, 76].iter() {
+        crust.note_on(*note, 127);
+    }
+    assert!(crust.generate_samples(2000).iter().any(|frame| frame[0].abs() > 1.0));
+
+    // Frames already in the limiter's lookahead were mixed before the switch.
+    crust.soft_clipping = 1.0;
+    let latency = crust.latency() as usize;
+    assert!(crust.generate_samples(2000)[latency..].iter().all(|frame| frame[0].abs() <= 1.0));
+}
+
+/// Loudest sample in the first 0.1 seconds of the notes played together.
+#[cfg(test)]
+fn chord_peak(notes: &[u8], normalization: f32) -> f32 {
+    let mut crust = Crust { normalization, ..Default::default() };
+    for &note in notes {
+        crust.note_on(note, 127);
+    }
+
+    crust.generate_samples(4410).iter().fold(0.0, |peak, frame| peak.max(frame[0].abs()))
+}
+
+#[test]
+fn test_voice_normalization() {
+    let single = chord_peak(&[60], 1.0);
+    assert_eq!(single, chord_peak(&[60], 0.0));
+    assert!(chord_peak(&[60, 64, 67], 0.0) > single * 1.5);
+    assert!(chord_peak(&[60, 64, 67], 0.5) <= single * 3.0_f32.sqrt());
+    assert!(chord_peak(&[60, 64, 67], 1.0) <= single);
+}
+
+#[test]
+fn test_aftertouch() {
+    let mut crust = Crust { aftertouch_target: 0.5, ..Default::default() };
+    assert_eq!(crust.get_parameter_text(65), "Cutoff");
+    crust.process_midi_data([144, 60, 100]);
+    crust.process_midi_data([208, 90, 0]);
+    assert_eq!(crust.aftertouch, 90.0 / 127.0);
+    assert_eq!(crust.expression(), [0.0, 90.0 / 127.0, 0.0]);
+
+    crust.generate_samples(1);
+    assert!((crust.filter.cutoff_mod - 0.5 * 90.0 / 127.0).abs() < 1e-6);
+
+    crust.aftertouch_target = 1.0;
+    assert_eq!(crust.expression(), [0.0, 0.0, 90.0 / 127.0]);
+    crust.process_midi_data([208, 0, 0]);
+    assert_eq!(crust.expression(), [0.0; 3]);
+}
+
+#[test]
+fn test_mod_wheel() {
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_parameter_text(66), "Vibrato");
+    crust.process_midi_data([176, 1, 127]);
+    assert!((crust.mod_wheel - 1.0).abs() < 1e-6);
+    assert_eq!(crust.expression(), [1.0, 0.0, 0.0]);
+
+    // The wheel adds vibrato even with the Lfo depth at 0, so at the top of
+    // the Lfo the voice runs sharp.
+    let frequency = |wheel: u8| {
+        let mut crust = Crust::default();
+        crust.process_midi_data([176, 1, wheel]);
+        crust.note_on(69, 127);
+        crust.lfo.phase = 0.25;
+        crust.generate_samples(1);
+        crust.notes[0].phases[0][0] * crust.sample_rate
+    };
+    assert!((frequency(0) - 440.0).abs() < 1e-6);
+    assert!(frequency(127) > 480.0);
+
+    crust.process_midi_data([176, 1, 0]);
+    crust.set_parameter(66, 0.5);
+    crust.process_midi_data([176, 1, 64]);
+    assert_eq!(crust.expression(), [0.0, 64.0 / 127.0, 0.0]);
+}
+
+#[test]
+fn test_controller_mapping() {
+    let mut crust = Crust::default();
+    crust.map_controller(74, Some(14));
+    crust.process_midi_data([176, 74, 127]);
+    assert!((crust.filter.cutoff - 1.0).abs() < 1e-6);
+    crust.process_midi_data([176, 74, 0]);
+    assert_eq!(crust.filter.cutoff, 0.0);
+
+    let mut loaded = Crust::default();
+    loaded.load_preset_data(&crust.get_preset_data());
+    assert_eq!(loaded.cc_map.get(&74), Some(&14));
+    loaded.process_midi_data([176, 74, 127]);
+    assert!((loaded.filter.cutoff - 1.0).abs() < 1e-6);
+
+    crust.map_controller(74, None);
+    crust.process_midi_data([176, 74, 127]);
+    assert_eq!(crust.filter.cutoff, 0.0);
+}
+
+#[test]
+fn test_set_parameter_clamps_values() {
+    let mut crust = Crust::default();
+    let defaults: Vec<f32> = (0..crust.get_info().parameters).map(|index| crust.get_parameter(index)).collect();
+    for index in 0..crust.get_info().parameters {
+        crust.set_parameter(index, f32::NAN);
+        crust.set_parameter(index, f32::INFINITY);
+        assert_eq!(crust.get_parameter(index), defaults[index as usize]);
+    }
+
+    crust.set_parameter(7, -3.0);
+    assert_eq!(crust.envelope.attack, 0.0);
+    crust.set_parameter(7, 40.0);
+    assert_eq!(crust.envelope.attack, 5.0);
+    crust.set_parameter(2, -1.0);
+    assert_eq!(crust.oscillators[0].detune.target, -100.0);
+    crust.set_parameter(11, 2.0);
+    assert_eq!(crust.master_vol, 1.0);
+}
+
+#[test]
+fn test_hard_sync() {
+    let mut crust = Crust { hard_sync: 1.0, ..Default::default() };
+    crust.set_sample_rate(48000.0);
+    crust.oscillators[1].detune = SmoothedParam::new(70.0);
+    crust.note_on(57, 127);
+
+    let dt1 = 220.0 / 48000.0;
+    let mut osc1_wraps = Vec::new();
+    let mut osc2_resets = Vec::new();
+    let mut last = crust.notes[0].phases[0];
+    for sample in 0..4800 {
+        crust.generate_samples(1);
+        let phases = crust.notes[0].phases[0];
+        if phases[0] < last[0] {
+            osc1_wraps.push(sample);
+        }
+        if phases[1] < last[1] {
+            osc2_resets.push(sample);
+            // Every reset lands right at the start of a cycle.
+            assert!(phases[1] < 2.0 * dt1);
+        }
+        last = phases;
+    }
+
+    assert_eq!(osc1_wraps.len(), 22);
+    for wrap in osc1_wraps.iter() {
+        assert!(osc2_resets.contains(wrap));
+    }
+    assert_eq!(crust.get_parameter_text(67), "On");
+}
+
+#[test]
+fn test_wavetable_interpolation() {
+    let wavetable = Wavetable { tables: vec![vec![0.0, 1.0, 0.0, -1.0], vec![0.5; 4]] };
+    assert_eq!(Wavetable::read(&wavetable.tables[0], 0.125), 0.5);
+    assert_eq!(Wavetable::read(&wavetable.tables[0], 0.875), -0.5);
+    assert_eq!(wavetable.sample(0.0, 0.25), 1.0);
+    assert_eq!(wavetable.sample(1.0, 0.25), 0.5);
+    assert_eq!(wavetable.sample(0.25, 0.25), 0.875);
+    assert_eq!(wavetable.sample(0.5, 0.125), 0.5);
+
+    let built_in = Wavetable::default();
+    assert_eq!(built_in.tables.len(), 4);
+    for table in built_in.tables.iter() {
+        assert_eq!(table.len(), WAVETABLE_SIZE);
+        let peak = table.iter().fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        assert!((peak - 1.0).abs() < 1e-6);
+    }
+    assert!((built_in.sample(0.0, 0.25) - 1.0).abs() < 1e-3);
+
+    let oscillator = Oscillator { wave_index: 4, table_position: 1.0, ..Default::default() };
+    let wave = create_wave(&oscillator, &built_in, 0.1, 0.01, 0.5);
+    assert_eq!(wave, 0.5 * built_in.sample(1.0, 0.1));
+}
+
+#[test]
+fn test_plugin_info() {
+    let crust = Crust::default();
+    let info = crust.get_info();
+    assert_eq!(info.vendor, "Randy Rollofson");
+    assert_eq!(info.unique_id, 0x6352_5354);
+    assert_eq!(info.version, 10);
+    assert!(info.f64_precision);
+    assert_eq!(info.inputs, 0);
+    assert_eq!(info.outputs, 2);
+
+    // Every parameter up to the count has a name and nothing past it does.
+    let named = (0..1000).filter(|&index| !crust.get_parameter_name(index).is_empty()).count();
+    assert_eq!(named as i32, info.parameters);
+    assert!(!crust.get_parameter_name(info.parameters - 1).is_empty());
+}
+
+#[test]
+fn test_parameter_table() {
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_info().parameters, PARAMETERS.len() as i32);
+    for index in 0..crust.get_info().parameters {
+        assert!(!crust.get_parameter_name(index).is_empty());
+        assert!(!crust.get_parameter_text(index).is_empty());
+        let value = crust.get_parameter(index);
+        crust.set_parameter(index, value);
+        assert!((crust.get_parameter(index) - value).abs() < 1e-6);
+    }
+
+    assert_eq!(crust.get_parameter_name(-1), "");
+    assert_eq!(crust.get_parameter(PARAMETERS.len() as i32), 0.0);
+}
+
+#[test]
+fn test_effects_mix_bypass() {
+    let mut plain = Crust { effects_mix: 0.0, ..Default::default() };
+    let mut processed = Crust {
+        effects_mix: 0.0,
+        distortion: 0.8,
+        ..Default::default()
+    };
+    processed.filter.cutoff = 0.1;
+    processed.filter.resonance = 0.9;
+    processed.chorus.mix = 1.0;
+    processed.delay.mix = 1.0;
+    processed.delay.feedback = 0.8;
+    processed.bitcrusher.bit_depth = 0.0;
+    for crust in [&mut plain, &mut processed].iter_mut() {
+        crust.note_on(60, 100);
+    }
+    assert_eq!(processed.generate_samples(2000), plain.generate_samples(2000));
+
+    processed.set_parameter(70, 1.0);
+    plain.set_parameter(70, 1.0);
+    assert!(processed.generate_samples(2000) != plain.generate_samples(2000));
+    assert_eq!(processed.get_parameter_text(70), "100%");
+}
+
+#[test]
+fn test_oscillator_transpose() {
+    let mut crust = Crust::default();
+    crust.set_parameter(71, 0.5 + 12.0 / 72.0);
+    assert_eq!(crust.oscillators[0].transpose, 12);
+    assert_eq!(crust.get_parameter_text(71), "+12 semitones");
+    crust.set_parameter(72, 0.0);
+    assert_eq!(crust.get_parameter_text(72), "-36 semitones");
+    crust.set_parameter(72, 0.5);
+
+    crust.note_on(57, 127);
+    crust.generate_samples(1);
+    let phases = crust.notes[0].phases[0];
+    assert!((phases[0] / phases[1] - 2.0).abs() < 1e-9);
+    assert!((phases[0] * crust.sample_rate - 440.0).abs() < 1e-6);
+
+    let oscillator = Oscillator { transpose: 36, ..Default::default() };
+    assert_eq!(oscillator.transposed(100), 127);
+}
+
+#[test]
+fn test_oscillator_phase_offset() {
+    let mut crust = Crust::default();
+    crust.set_parameter(74, 0.5);
+    assert_eq!(crust.get_parameter_text(74), "180°");
+
+    crust.note_on(69, 127);
+    assert_eq!(crust.notes[0].phases[0], [0.0, 0.5]);
+    let output = crust.generate_samples(4410);
+    assert!(output.iter().all(|frame| frame[0].abs() < 1e-4 && frame[1].abs() < 1e-4));
+
+    let mut in_phase = Crust::default();
+    in_phase.note_on(69, 127);
+    assert!(in_phase.generate_samples(4410).iter().any(|frame| frame[0].abs() > 0.1));
+}
+
+#[test]
+fn test_denormal_flushing() {
+    assert_eq!(flush_denormal(1e-21), 0.0);
+    assert_eq!(flush_denormal(-1e-25), 0.0);
+    assert_eq!(flush_denormal(1e-10), 1e-10);
+
+    let mut filter = Filter { cutoff: 0.3, resonance: 0.9, ..Default::default() };
+    filter.process(1.0, 0, 44100.0);
+    for _ in 0..200000 {
+        filter.process(0.0, 0, 44100.0);
+    }
+    assert_eq!(filter.ic1eq[0], 0.0);
+    assert_eq!(filter.ic2eq[0], 0.0);
+
+    let mut delay = Delay { time: 0.001, feedback: 1.0, mix: 1.0, ..Default::default() };
+    delay.resize(44100.0);
+    delay.process([1.0, 1.0], 44100.0, DEFAULT_TEMPO);
+    let mut tail = [1.0; 2];
+    for _ in 0..60000 {
+        tail = delay.process([0.0, 0.0], 44100.0, DEFAULT_TEMPO);
+    }
+    assert_eq!(tail, [0.0, 0.0]);
+
+    let mut param = SmoothedParam::new(1.0);
+    param.target = 0.0;
+    for _ in 0..100000 {
+        param.next(smoothing_coefficient(44100.0));
+    }
+    assert_eq!(param.current, 0.0);
+}
+
+#[test]
+fn test_glide_legato() {
+    let mut crust = Crust { max_voices: 1, glide_time: 0.1, glide_legato: 1.0, ..Default::default() };
+    crust.process_midi_data([144, 57, 100]);
+    crust.process_midi_data([128, 57, 0]);
+    crust.process_midi_data([144, 69, 100]);
+    assert_eq!(crust.current_freq, 440.0);
+
+    // Playing the next note before letting go of this one slides to it.
+    crust.process_midi_data([144, 57, 100]);
+    assert_eq!(crust.target_freq, 220.0);
+    assert_eq!(crust.current_freq, 440.0);
+    crust.generate_samples(2205);
+    assert!((crust.current_freq - 311.127).abs() < 0.01);
+
+    crust.set_parameter(77, 0.0);
+    crust.process_midi_data([128, 57, 0]);
+    crust.process_midi_data([128, 69, 0]);
+    crust.process_midi_data([144, 69, 100]);
+    assert!(crust.current_freq < 440.0);
+}
+
+/// The first 1000 samples of note 69 with the given oscillator volumes,
+/// with oscillator 2 on a saw so the two can be told apart.
+#[cfg(test)]
+fn balanced_output(volumes: [f32; 2], balance: Option<f32>) -> Vec<[f32; 2]> {
+    let mut crust = Crust::default();
+    crust.oscillators[1].wave_index = 1;
+    crust.oscillators[0].volume = SmoothedParam::new(volumes[0]);
+    crust.oscillators[1].volume = SmoothedParam::new(volumes[1]);
+    if let Some(balance) = balance {
+        crust.balance_mode = 1.0;
+        crust.osc_balance = SmoothedParam::new(balance);
+    }
+    crust.note_on(69, 127);
+    crust.generate_samples(1000)
 }
 
 #[test]
-fn test_sawtooth_wave() {
-    assert_eq!(create_sawtooth_wave(0, 0.0, 0.0, 0.0), 0.0);
-    assert_eq!(create_sawtooth_wave(69, 0.0, 0.0, 0.0), 0.0);
-    assert_eq!(create_sawtooth_wave(69, 1.0, 0.00454545454, 0.0), 0.5);
-    assert_eq!(create_sawtooth_wave(69, 1.0, 0.00454545455, 0.0), -0.5);
+fn test_osc_balance() {
+    assert_eq!(balanced_output([0.9, 0.1], Some(0.5)), balanced_output([0.5, 0.5], None));
+    assert_eq!(balanced_output([0.2, 0.7], Some(0.0)), balanced_output([1.0, 0.0], None));
+    assert_eq!(balanced_output([0.2, 0.7], Some(1.0)), balanced_output([0.0, 1.0], None));
+
+    let mut crust = Crust::default();
+    crust.set_parameter(78, 1.0);
+    crust.set_parameter(79, 0.25);
+    assert_eq!(crust.get_parameter_text(78), "Balance");
+    assert_eq!(crust.get_parameter_text(79), "75% / 25%");
 }
 
 #[test]
-fn test_square_wave() {
-    assert_eq!(create_square_wave(0, 0.0, 0.0, 0.0), 0.0);
-    assert_eq!(create_square_wave(69, 0.0, 0.0, 0.0), 0.0);
-    assert_eq!(create_square_wave(69, 1.0, 0.0005682, 0.0), 0.4);
-    assert_eq!(create_square_wave(69, 1.0, 0.0017045, 0.0), -0.4);
+fn test_tail_size() {
+    let mut crust = Crust { sample_rate: 48000.0, ..Default::default() };
+    crust.envelope.release = 0.0;
+    assert_eq!(crust.get_tail_size(), 1);
+
+    crust.envelope.release = 0.5;
+    assert_eq!(crust.get_tail_size(), 24000);
+
+    // Half feedback needs 10 repeats to drop below -60 dB, plus the first echo.
+    crust.delay = Delay { time: 0.25, feedback: 0.5, mix: 0.5, ..Default::default() };
+    assert_eq!(crust.get_tail_size(), 24000 + 12000 * 11);
+
+    crust.delay.feedback = 0.0;
+    assert_eq!(crust.get_tail_size(), 24000 + 12000);
 }
 
 #[test]
-fn test_triangle_wave() {
-    assert_eq!(create_triangle_wave(0, 0.0, 0.0, 0.0), 0.0);
-    assert_eq!(create_triangle_wave(69, 0.0, 0.0, 0.0), 0.0);
-    assert_eq!(create_sine_wave(69, 1.0, 0.0005682, 0.0), 1.0);
-    assert_eq!(create_sine_wave(69, 1.0, 0.0017045, 0.0), -1.0);
+fn test_reverb_impulse() {
+    let mut reverb = Reverb { mix: 1.0, ..Default::default() };
+    reverb.resize(48000.0);
+    assert_eq!(reverb.combs[0][0].buffer.len(), 1214);
+    assert_eq!(reverb.combs[1][0].buffer.len(), 1239);
+
+    let mut output = vec![reverb.process([1.0, 1.0])];
+    for _ in 0..96000 {
+        output.push(reverb.process([0.0, 0.0]));
+    }
+
+    // Nothing comes out until the shortest comb has gone round once.
+    assert!(output[..240].iter().all(|frame| *frame == [0.0, 0.0]));
+    let energy = |frames: &[[f32; 2]]| frames.iter().map(|frame| frame[0] * frame[0]).sum::<f32>();
+    let windows: Vec<f32> = output.chunks(12000).map(energy).collect();
+    assert!(windows[0] > 0.0);
+    for pair in windows.windows(2) {
+        assert!(pair[1] < pair[0]);
+    }
+
+    // The tail is dense rather than a handful of echoes, and the sides differ.
+    let busy = output[4800..9600].iter().filter(|frame| frame[0].abs() > 1e-6).count();
+    assert!(busy > 4000);
+    assert!(output[4800..9600].iter().any(|frame| frame[0] != frame[1]));
+    assert!(output[95000..].iter().all(|frame| frame[0].abs() < 1e-3));
 }
 
 #[test]
-fn test_midi_note_num_to_freq() {
-    assert_eq!(midi_note_num_to_freq(21, 0.0), 27.5);
-    assert_eq!(midi_note_num_to_freq(33, 0.0), 55.0);
-    assert_eq!(midi_note_num_to_freq(45, 0.0), 110.0);
-    assert_eq!(midi_note_num_to_freq(57, 0.0), 220.0);
-    assert_eq!(midi_note_num_to_freq(69, 0.0), 440.0);
-    assert_eq!(midi_note_num_to_freq(81, 0.0), 880.0);
-    assert_eq!(midi_note_num_to_freq(93, 0.0), 1760.0);
-    assert_eq!(midi_note_num_to_freq(105, 0.0), 3520.0);
+fn test_stereo_width() {
+    assert_eq!(stereo_width([0.8, 0.2], 1.0), [0.8, 0.2]);
+    assert_eq!(stereo_width([0.8, 0.2], 0.0), [0.5, 0.5]);
+    let wide = stereo_width([0.8, 0.2], 2.0);
+    assert!((wide[0] - 1.1).abs() < 1e-6 && (wide[1] + 0.1).abs() < 1e-6);
+
+    let mut crust = Crust::default();
+    crust.oscillators[0].pan = 0.0;
+    crust.oscillators[1].pan = 1.0;
+    crust.oscillators[1].wave_index = 1;
+    crust.set_parameter(83, 0.0);
+    assert_eq!(crust.get_parameter_text(83), "0%");
+    crust.note_on(60, 100);
+    let output = crust.generate_samples(1000);
+    assert!(output.iter().all(|frame| frame[0] == frame[1]));
+    assert!(output.iter().any(|frame| frame[0] != 0.0));
 }
 
 #[test]
-fn test_distortion() {
-    assert_eq!(distortion(0.75, 0.0, 1.0), 0.75);
-    assert_eq!(distortion(0.75, 0.32, 1.0), -12.776747);
-    assert_eq!(distortion(0.75, 0.50, 1.0), -20.385542);
-    assert_eq!(distortion(0.75, 0.75, 1.0), -30.953312);
-    assert_eq!(distortion(0.75, 1.0, 1.0), -41.521084);
+fn test_notes_added_between_buffers() {
+    let mut crust = Crust { max_voices: 4, ..Default::default() };
+    crust.envelope.release = 0.01;
+    for buffer in 0..200 {
+        let note = 40 + (buffer * 7 % 48) as u8;
+        crust.process_midi_data([144, note, 100]);
+        if buffer % 3 == 0 {
+            crust.process_midi_data([128, note, 0]);
+        }
+        if buffer == 100 {
+            crust.arpeggiator.enabled = 1.0;
+            crust.arpeggiator.rate = 1.0;
+        }
+
+        let output = crust.generate_samples(buffer % 64);
+        assert!(output.iter().all(|frame| frame[0].is_finite() && frame[1].is_finite()));
+        assert!(crust.active_voices() <= 4);
+    }
+}
+
+/// Aliasing left after a loud 4.3 kHz sine goes through full distortion
+/// with the given oversampling slider value.
+#[cfg(test)]
+fn distortion_aliasing(oversampling: f32) -> f64 {
+    let mut crust = Crust { distortion: 1.0, oversampling, ..Default::default() };
+    let n = 1024;
+    let wave: Vec<f32> = (0..2 * n).map(|i| {
+        let input = 0.8 * (2.0 * PI * (101 * i) as f64 / n as f64).sin() as f32;
+        crust.oversampled_saturate(input, 0)
+    }).collect();
+
+    // Skip the first cycle while the filters settle.
+    inharmonic_energy(&wave[n..], 101)
 }
 
 #[test]
-fn test_overdrive() {
-    assert_eq!(overdrive(0.0), 0.0);
-    assert_eq!(overdrive(0.32), 0.64);
-    assert_eq!(overdrive(0.50), 0.5285955);
-    assert_eq!(overdrive(0.75), 1.0);
-    assert_eq!(overdrive(1.0), 1.0);
+fn test_oversampling_reduces_aliasing() {
+    let plain = distortion_aliasing(0.0);
+    let twice = distortion_aliasing(0.5);
+    let four_times = distortion_aliasing(1.0);
+    assert!(twice < plain);
+    assert!(four_times < plain / 10.0);
+
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_parameter_text(84), "1x");
+    crust.set_parameter(84, 1.0);
+    assert_eq!(crust.get_parameter_text(84), "4x");
+}
+
+#[test]
+fn test_envelope_time_text() {
+    let mut crust = Crust::default();
+    crust.set_parameter(7, 0.05);
+    assert_eq!(crust.get_parameter_text(7), "250 ms");
+    crust.set_parameter(10, 0.0);
+    assert_eq!(crust.get_parameter_text(10), "0 ms");
+    crust.set_parameter(8, 0.5);
+    assert_eq!(crust.get_parameter_text(8), "2.50 s");
+    crust.set_parameter(41, 1.0);
+    assert_eq!(crust.get_parameter_text(41), "5.00 s");
+    crust.set_parameter(22, 0.05);
+    assert_eq!(crust.get_parameter_text(22), "100 ms");
+    crust.set_parameter(22, 1.0);
+    assert_eq!(crust.get_parameter_text(22), "2.00 s");
+}
+
+/// Loudest sample of a held sine at the given velocity and velocity to envelope amount.
+#[cfg(test)]
+fn velocity_peak(velocity: u8, amount: f32) -> f32 {
+    let mut crust = Crust { velocity_to_envelope: amount, ..Default::default() };
+    crust.envelope.attack = 0.001;
+    crust.envelope.sustain = 1.0;
+    crust.note_on(69, velocity);
+    crust.generate_samples(4410).iter().fold(0.0, |peak, frame| peak.max(frame[0].abs()))
+}
+
+#[test]
+fn test_velocity_to_envelope() {
+    let full = velocity_peak(127, 0.5);
+    assert!((velocity_peak(64, 0.5) / full - (0.5 + 0.5 * 64.0 / 127.0)).abs() < 1e-3);
+    assert!((velocity_peak(64, 0.0) / full - 1.0).abs() < 1e-3);
+    assert!((velocity_peak(64, 1.0) / full - 64.0 / 127.0).abs() < 1e-3);
+    assert_eq!(velocity_to_peak(0, 1.0), 0.0);
+}
+
+#[test]
+fn test_latch() {
+    let mut crust = Crust::default();
+    crust.envelope.release = 0.01;
+    crust.set_parameter(86, 1.0);
+    crust.process_midi_data([144, 60, 100]);
+    crust.process_midi_data([128, 60, 0]);
+    crust.generate_samples(1000);
+    assert_eq!(crust.notes.len(), 1);
+    assert!(crust.notes[0].note_on);
+
+    crust.process_midi_data([144, 60, 100]);
+    assert!(!crust.notes[0].note_on);
+    crust.process_midi_data([128, 60, 0]);
+    crust.generate_samples(1000);
+    assert!(crust.notes.is_empty());
+
+    // Turning latch off lets go of whatever is still latched.
+    crust.process_midi_data([144, 64, 100]);
+    crust.process_midi_data([128, 64, 0]);
+    crust.set_parameter(86, 0.0);
+    assert!(!crust.notes[0].note_on);
+    assert!(crust.latched_notes.is_empty());
+}
+
+#[test]
+fn test_bend_range_parameter() {
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_parameter_text(87), "±2 semitones");
+    crust.set_parameter(87, 0.5);
+    assert_eq!(crust.bend_range, 12.0);
+    assert_eq!(crust.get_parameter_text(87), "±12 semitones");
+
+    crust.process_midi_data([224, 127, 127]);
+    crust.note_on(57, 127);
+    crust.generate_samples(1);
+    assert!((crust.notes[0].phases[0][0] * crust.sample_rate - 440.0).abs() < 1e-6);
+
+    // Changing the range moves the bend that is already held.
+    crust.set_parameter(87, 1.0);
+    assert_eq!(crust.bend_range, 24.0);
+    crust.note_on(45, 127);
+    crust.generate_samples(1);
+    assert!((crust.notes[1].phases[0][0] * crust.sample_rate - 440.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_noise_only() {
+    let mut crust = Crust { noise_only: 1.0, sub_level: 1.0, ..Default::default() };
+    crust.envelope.attack = 0.001;
+    crust.envelope.decay = 0.05;
+    crust.envelope.sustain = 0.0;
+    crust.note_on(69, 127);
+    assert!(crust.generate_samples(4410).iter().all(|frame| frame[0] == 0.0));
+
+    // Noise comes through shaped by the envelope, then dies away with the decay.
+    crust.all_sound_off();
+    crust.noise = 1.0;
+    crust.noise_generator.color = 0.0;
+    crust.note_on(69, 127);
+    let output = crust.generate_samples(4410);
+    assert!(output[..2000].iter().any(|frame| frame[0].abs() > 0.01));
+    assert!(output[3000..].iter().all(|frame| frame[0] == 0.0));
+}
+
+#[test]
+fn test_phase_stays_accurate_for_an_hour() {
+    // An hour of a 440 Hz oscillator at 44.1 kHz, compared against the exact
+    // phase worked out with whole numbers.
+    let dt = 440.0 / 44100.0;
+    let mut phase = 0.0;
+    let mut worst: f64 = 0.0;
+    for n in 1..=44100 * 3600_u64 {
+        phase = advance_phase(phase, dt);
+        if n % 44100 == 7 {
+            let exact = (n * 440 % 44100) as f64 / 44100.0;
+            let error = (phase - exact).abs();
+            worst = worst.max(error.min(1.0 - error));
+        }
+    }
+
+    assert!((0.0..1.0).contains(&phase));
+    assert!(worst < 1e-6);
+}
+
+/// RMS level of note 69 after a quarter second, through the distortion at
+/// the given amount.
+#[cfg(test)]
+fn driven_rms(distortion: f32, auto_gain: f32) -> f32 {
+    let mut crust = Crust { distortion, auto_gain, ..Default::default() };
+    crust.note_on(69, 127);
+    crust.generate_samples(11025);
+    let output = crust.generate_samples(4410);
+
+    (output.iter().map(|frame| frame[0] * frame[0]).sum::<f32>() / output.len() as f32).sqrt()
+}
+
+#[test]
+fn test_drive_auto_gain() {
+    let clean = driven_rms(0.0, 1.0);
+    let boosted = driven_rms(1.0, 0.0);
+    let compensated = driven_rms(1.0, 1.0);
+    let decibels = |level: f32| 20.0 * (level / clean).log10();
+    assert!(decibels(boosted) > 3.0);
+    assert!(decibels(compensated).abs() < 1.0);
+    assert!((driven_rms(0.0, 0.0) - clean).abs() < 1e-4);
+}
+
+/// RMS level of white noise alone, with the oscillators muted but set to the given volumes.
+#[cfg(test)]
+fn relative_noise_rms(volume: f32, relative_noise: f32) -> f32 {
+    let mut crust = Crust { noise: 1.0, noise_only: 1.0, relative_noise, ..Default::default() };
+    crust.noise_generator.color = 0.0;
+    crust.oscillators[0].volume = SmoothedParam::new(volume);
+    crust.oscillators[1].volume = SmoothedParam::new(volume);
+    crust.note_on(69, 127);
+    let output = crust.generate_samples(44100);
+
+    (output.iter().map(|frame| frame[0] * frame[0]).sum::<f32>() / output.len() as f32).sqrt()
+}
+
+#[test]
+fn test_relative_noise() {
+    let quiet = relative_noise_rms(0.25, 1.0);
+    let loud = relative_noise_rms(0.5, 1.0);
+    assert!((loud / quiet - 2.0).abs() < 0.1);
+
+    let independent = relative_noise_rms(0.25, 0.0) / relative_noise_rms(0.5, 0.0);
+    assert!((independent - 1.0).abs() < 0.05);
+}
+
+#[test]
+fn test_process_f64() {
+    let mut single = Crust::default();
+    let mut double = Crust::default();
+    for crust in [&mut single, &mut double].iter_mut() {
+        crust.note_on(60, 100);
+        crust.note_on(67, 80);
+    }
+
+    let inputs = vec![vec![0.0_f32; 512]; 2];
+    let mut outputs = vec![vec![0.0_f32; 512]; 2];
+    single.process(&mut HostBuffer::new(2, 2).bind(&inputs, &mut outputs));
+
+    let inputs_f64 = vec![vec![0.0_f64; 512]; 2];
+    let mut outputs_f64 = vec![vec![0.0_f64; 512]; 2];
+    double.process_f64(&mut HostBuffer::new(2, 2).bind(&inputs_f64, &mut outputs_f64));
+
+    for (channel, output) in outputs.iter().enumerate() {
+        assert!(output.iter().any(|sample| sample.abs() > 0.01));
+        for (sample, wide) in output.iter().zip(outputs_f64[channel].iter()) {
+            assert!((*sample as f64 - wide).abs() < 1e-6);
+        }
+    }
+}
+
+/// RMS level of a held note with only the given oscillator turned up.
+#[cfg(test)]
+fn split_rms(note: u8, oscillator: usize) -> f32 {
+    let mut crust = Crust { split: 1.0, split_point: 60, ..Default::default() };
+    crust.oscillators[0].volume = SmoothedParam::new(0.0);
+    crust.oscillators[1].volume = SmoothedParam::new(0.0);
+    crust.oscillators[oscillator].volume = SmoothedParam::new(0.5);
+    crust.note_on(note, 127);
+    let output = crust.generate_samples(4410);
+
+    (output.iter().map(|frame| frame[0] * frame[0]).sum::<f32>() / output.len() as f32).sqrt()
+}
+
+#[test]
+fn test_keyboard_split() {
+    // Below the split only oscillator 1 sounds.
+    assert!(split_rms(48, 0) > 0.01);
+    assert_eq!(split_rms(48, 1), 0.0);
+
+    // From the split point up only oscillator 2 sounds.
+    assert_eq!(split_rms(60, 0), 0.0);
+    assert!(split_rms(72, 1) > 0.01);
+
+    let mut crust = Crust::default();
+    crust.set_parameter(92, 60.0 / 127.0);
+    assert_eq!(crust.get_parameter_text(92), "C4");
+    assert_eq!(note_name(69), "A4");
+    assert_eq!(note_name(0), "C-1");
+}
+
+#[test]
+fn test_osc2_waveform_text() {
+    let mut crust = Crust::default();
+    crust.set_parameter(0, waveform_value(0));
+    crust.set_parameter(3, waveform_value(2));
+    assert_eq!(crust.get_parameter_text(0), WAVEFORM_NAMES[0]);
+    assert_eq!(crust.get_parameter_text(3), WAVEFORM_NAMES[2]);
+}
+
+#[test]
+fn test_panic() {
+    let mut crust = Crust::default();
+    crust.filter.resonance = 0.9;
+    crust.delay.mix = 1.0;
+    crust.delay.feedback = 0.9;
+    crust.reverb.mix = 1.0;
+    crust.chorus.mix = 1.0;
+    crust.note_on(60, 127);
+    crust.note_on(64, 127);
+    crust.generate_samples(4410);
+
+    crust.panic();
+    assert!(crust.notes.is_empty());
+    assert!(crust.generate_samples(44100).iter().all(|frame| frame[0] == 0.0 && frame[1] == 0.0));
+}
+
+#[test]
+fn test_drift() {
+    // The same seed always wanders the same way, and different seeds wander apart.
+    let mut first = Drift::new(3);
+    let mut again = Drift::new(3);
+    let mut other = Drift::new(4);
+    for _ in 0..44100 {
+        let value = first.next(44100.0);
+        assert_eq!(value, again.next(44100.0));
+        assert!(value.iter().all(|offset| offset.abs() <= 1.0));
+    }
+    assert_ne!(first.next(44100.0), other.next(44100.0));
+
+    // A synth that has already used up some drift seeds plays a note exactly
+    // like a fresh one with drift off, and differently with drift on.
+    let render = |drift: f32| {
+        let mut crust = Crust { drift, ..Default::default() };
+        crust.note_on(60, 127);
+        let clean = crust.generate_samples(4410);
+        crust.panic();
+        crust.note_on(60, 127);
+        (clean, crust.generate_samples(4410))
+    };
+    let (clean, used) = render(0.0);
+    assert_eq!(clean, used);
+    let (clean, used) = render(1.0);
+    assert_ne!(clean, used);
+}
+
+#[test]
+fn test_process_without_inputs() {
+    let mut crust = Crust::default();
+    crust.note_on(69, 127);
+
+    let inputs: Vec<Vec<f32>> = Vec::new();
+    let mut outputs = vec![vec![0.0_f32; 512]; 2];
+    crust.process(&mut HostBuffer::new(0, 2).bind(&inputs, &mut outputs));
+
+    for output in outputs.iter() {
+        assert!(output.iter().any(|sample| sample.abs() > 0.01));
+    }
+    assert_eq!(outputs[0], outputs[1]);
+}
+
+#[test]
+fn test_meter() {
+    let sine: Vec<[f32; 2]> = (0..44100)
+        .map(|n| {
+            let value = 0.5 * (2.0 * PI * 440.0 * n as f64 / 44100.0).sin() as f32;
+            [value, value]
+        })
+        .collect();
+    let mut meter = Meter::default();
+    for block in sine.chunks(512).cycle().take(3 * sine.len() / 512) {
+        meter.update(block, 44100.0);
+    }
+    assert!((meter.rms() / 0.5 - 0.707).abs() < 0.01);
+    assert!((meter.peak - 0.5).abs() < 0.01);
+
+    // Silence lets the levels fall back gradually rather than dropping to zero.
+    meter.update(&[[0.0; 2]; 4410], 44100.0);
+    assert!(meter.rms() > 0.1 && meter.rms() < 0.35);
+    assert!(meter.peak > 0.1 && meter.peak < 0.5);
+    meter.update(&vec![[0.0; 2]; 441000], 44100.0);
+    assert!(meter.peak < 1e-6 && meter.rms() < 1e-6);
+
+    let mut crust = Crust::default();
+    crust.note_on(69, 127);
+    let mut outputs = vec![vec![0.0_f32; 512]; 2];
+    crust.process(&mut HostBuffer::new(0, 2).bind(&Vec::<Vec<f32>>::new(), &mut outputs));
+    assert!(crust.output_peak() > 0.01);
+    assert!(crust.output_rms() > 0.0);
+}
+
+#[test]
+fn test_waveform_morph() {
+    let wavetable = Wavetable::default();
+    let mut oscillator = Oscillator { morph: 1.0, morph_position: 0.165, ..Default::default() };
+    let (index, amount) = morph_blend(0.165);
+    assert_eq!(index, 0);
+    assert!((amount - 0.5).abs() < 0.01);
+
+    // Halfway into the first region is an even mix of the sine and the saw.
+    for &phase in [0.1, 0.25, 0.6, 0.9].iter() {
+        let sine = create_sine_wave_from_phase(phase, 1.0);
+        let saw = create_sawtooth_wave_blep(phase, 0.01, 1.0);
+        let morphed = create_wave(&oscillator, &wavetable, phase, 0.01, 1.0);
+        assert!((morphed - (sine + saw) / 2.0).abs() < 0.01);
+    }
+
+    // The ends of the slider are the plain sine and triangle.
+    oscillator.morph_position = 0.0;
+    assert_eq!(create_wave(&oscillator, &wavetable, 0.1, 0.01, 1.0), create_sine_wave_from_phase(0.1, 1.0));
+    oscillator.morph_position = 1.0;
+    assert_eq!(create_wave(&oscillator, &wavetable, 0.1, 0.01, 1.0), create_triangle_wave_from_phase(0.1, 1.0));
+    assert_eq!(morph_text(0.5), "Saw to Square, 50%");
+}
+
+#[test]
+fn test_oscillator_enable() {
+    let mut disabled = Crust::default();
+    disabled.oscillators[0].enabled = 0.0;
+    let mut silent = Crust::default();
+    silent.oscillators[0].volume = SmoothedParam::new(0.0);
+    for crust in [&mut disabled, &mut silent].iter_mut() {
+        crust.oscillators[0].wave_index = 1;
+        crust.oscillators[1].wave_index = 2;
+        crust.note_on(57, 127);
+    }
+    let output = disabled.generate_samples(4410);
+    assert!(output.iter().any(|frame| frame[0].abs() > 0.01));
+    assert_eq!(output, silent.generate_samples(4410));
+
+    // Disabling the noise mutes it without touching its level.
+    let mut crust = Crust { noise: 1.0, noise_enabled: 0.0, noise_only: 1.0, ..Default::default() };
+    crust.note_on(57, 127);
+    assert!(crust.generate_samples(4410).iter().all(|frame| frame[0] == 0.0));
+    assert_eq!(crust.noise, 1.0);
+}
+
+#[test]
+fn test_fixed_frequency() {
+    let tuning = Tuning::default();
+    let oscillator = Oscillator { fixed: 1.0, fixed_freq: 0.0, ..Default::default() };
+    assert!((oscillator.frequency(40, 0.0, &tuning, 0.0) - 20.0).abs() < 1e-9);
+    assert_eq!(oscillator.frequency(40, 0.0, &tuning, 2.0), oscillator.frequency(90, 0.0, &tuning, 0.0));
+    assert!((Oscillator { fixed_freq: 1.0, ..Default::default() }.fixed_hz() - 20000.0).abs() < 1e-6);
+
+    // Any key plays the same pitch, and still opens the envelope.
+    let render = |note: u8| {
+        let mut crust = Crust::default();
+        crust.oscillators[1].volume = SmoothedParam::new(0.0);
+        crust.oscillators[0].fixed = 1.0;
+        crust.note_on(note, 127);
+        crust.generate_samples(4410)
+    };
+    let low = render(36);
+    assert!(low.iter().any(|frame| frame[0].abs() > 0.01));
+    assert_eq!(low, render(84));
+}
+
+#[test]
+fn test_limiter() {
+    let mut limiter = Limiter { enabled: 1.0, threshold: 0.75, ..Default::default() };
+    let threshold = 10.0_f32.powf(-6.0 / 20.0);
+
+    // A sine at twice full scale, after a quiet stretch, never gets past the threshold.
+    let mut output = Vec::new();
+    for n in 0..44100 {
+        let level = if n < 1000 { 0.1 } else { 2.0 };
+        let value = level * (2.0 * PI * 220.0 * n as f64 / 44100.0).sin() as f32;
+        output.push(limiter.process([value, -value], 44100.0));
+    }
+    assert!(output.iter().all(|frame| frame[0].abs() <= threshold + 1e-6 && frame[1].abs() <= threshold + 1e-6));
+    assert!(output[40000..].iter().any(|frame| frame[0].abs() > threshold * 0.99));
+
+    // Quiet input comes through untouched, just delayed by the lookahead.
+    let mut limiter = Limiter { enabled: 1.0, ..Default::default() };
+    let input: Vec<f32> = (0..1000).map(|n| 0.5 * (n as f32 * 0.05).sin()).collect();
+    let output: Vec<f32> = input.iter().map(|&value| limiter.process([value, value], 44100.0)[0]).collect();
+    assert_eq!(&output[limiter.latency()..], &input[..1000 - limiter.latency()]);
+
+    // Switched off, even loud input is only delayed.
+    let mut limiter = Limiter::default();
+    let output: Vec<f32> = input.iter().map(|&value| limiter.process([value * 4.0, value], 44100.0)[0]).collect();
+    assert!(output[limiter.latency()..].iter().zip(input.iter()).all(|(&out, &value)| out == value * 4.0));
+
+    let mut crust = Crust { master_vol: 1.0, ..Default::default() };
+    crust.limiter.enabled = 1.0;
+    crust.limiter.threshold = 0.75;
+    for note in [48, 52, 55, 60, 64].iter() {
+        crust.note_on(*note, 127);
+    }
+    let output = crust.generate_samples(44100);
+    assert!(output.iter().all(|frame| frame[0].abs() <= threshold + 1e-6 && frame[1].abs() <= threshold + 1e-6));
+}
+
+#[test]
+fn test_unison_detune_cents() {
+    let tuning = Tuning::default();
+    let oscillator = Oscillator::default();
+    let frequency = |cents: f32| oscillator.frequency(69, cents, &tuning, 0.0);
+
+    // Half spread puts the outer copies of seven 25 cents either side, on top
+    // of a 10 cent oscillator detune.
+    assert_eq!(unison_detune(0, 7, 0.5), -25.0);
+    assert_eq!(unison_detune(3, 7, 0.5), 0.0);
+    assert_eq!(unison_detune(6, 7, 0.5), 25.0);
+    let lowest = frequency(10.0 + unison_detune(0, 7, 0.5));
+    let middle = frequency(10.0 + unison_detune(3, 7, 0.5));
+    let highest = frequency(10.0 + unison_detune(6, 7, 0.5));
+    assert!((lowest - 440.0 * (-15.0_f64 / 1200.0).exp2()).abs() < 1e-9);
+    assert!((middle - 440.0 * (10.0_f64 / 1200.0).exp2()).abs() < 1e-9);
+    assert!((highest - 440.0 * (35.0_f64 / 1200.0).exp2()).abs() < 1e-9);
+
+    // The spread is the same musical interval at any pitch.
+    let low = oscillator.frequency(33, unison_detune(6, 7, 1.0), &tuning, 0.0) / oscillator.frequency(33, 0.0, &tuning, 0.0);
+    let high = oscillator.frequency(93, unison_detune(6, 7, 1.0), &tuning, 0.0) / oscillator.frequency(93, 0.0, &tuning, 0.0);
+    assert!((low - high).abs() < 1e-9);
+    assert!((high - (50.0_f64 / 1200.0).exp2()).abs() < 1e-9);
+}
+
+#[test]
+fn test_no_editor() {
+    let mut crust = Crust::default();
+    assert!(crust.get_editor().is_none());
+
+    // Values the host sets show up in the editor's handle.
+    let editor = crust.shared_parameters();
+    crust.set_parameter(14, 0.25);
+    assert_eq!(editor.get(14), 0.25);
+
+    // Values the editor sets from its own thread reach the host at once and
+    // the synth at the start of the next block.
+    let handle = crust.shared_parameters();
+    std::thread::spawn(move || {
+        handle.set(83, 0.75);
+        handle.set(0, 0.3);
+    }).join().unwrap();
+    assert_eq!(crust.get_parameter(83), 0.75);
+    let inputs: Vec<Vec<f32>> = Vec::new();
+    let mut outputs = vec![vec![0.0_f32; 64]; 2];
+    crust.process(&mut HostBuffer::new(0, 2).bind(&inputs, &mut outputs));
+    assert_eq!(crust.get_parameter_text(83), "150%");
+    assert_eq!(crust.get_parameter_text(0), "Saw");
+    for index in 0..PARAMETERS.len() {
+        assert_eq!(editor.get(index), crust.get_parameter(index as i32));
+    }
+}
+
+#[test]
+fn test_envelope_segment_curves() {
+    let envelope = Envelope { attack: 0.1, decay: 0.1, sustain: 0.5, release: 0.1, decay_curve: 1.0, ..Default::default() };
+    let mut voice = Voice::new(69, 127);
+
+    // A straight attack is halfway up halfway through.
+    voice.duration = 0.05;
+    assert!((get_amplitude(&envelope, &voice, 1.0) - 0.5).abs() < 1e-6);
+
+    // The exponential decay has done most of its fall by halfway, where a
+    // straight one would only be halfway down to the sustain at 0.75.
+    voice.duration = 0.15;
+    let level = get_amplitude(&envelope, &voice, 1.0);
+    assert!((level - (1.0 - envelope_curve(0.5, 1.0) * 0.5)).abs() < 1e-6);
+    assert!(level < 0.55);
+
+    // The release stays straight.
+    voice.note_on = false;
+    voice.end_time = 0.05;
+    assert!((generate_release(&envelope, &voice, 0.5) - 0.25).abs() < 1e-6);
+
+    // Only the attack curved, with a straight decay and release.
+    let envelope = Envelope { attack_curve: 1.0, decay_curve: 0.0, ..envelope };
+    voice.note_on = true;
+    voice.duration = 0.05;
+    assert!(get_amplitude(&envelope, &voice, 1.0) > 0.9);
+    voice.duration = 0.15;
+    assert!((get_amplitude(&envelope, &voice, 1.0) - 0.75).abs() < 1e-6);
+
+    let envelope = Envelope { attack_curve: 0.0, release_curve: 1.0, ..envelope };
+    voice.note_on = false;
+    voice.end_time = 0.05;
+    assert!(generate_release(&envelope, &voice, 0.5) < 0.05);
+}
+
+#[test]
+fn test_zero_length_envelope_segments() {
+    let envelope = Envelope { attack: 0.0, decay: 0.0, sustain: 0.5, release: 0.0, ..Default::default() };
+    let mut voice = Voice::new(69, 127);
+    assert_eq!(get_amplitude(&envelope, &voice, 1.0), 1.0);
+    voice.duration = 0.001;
+    assert_eq!(get_amplitude(&envelope, &voice, 1.0), 0.5);
+    voice.note_on = false;
+    voice.end_time = 0.0;
+    assert_eq!(generate_release(&envelope, &voice, 0.5), 0.0);
+
+    // An instant attack no longer fills the filter with NaN.
+    let mut crust = Crust { envelope, ..Default::default() };
+    crust.note_on(69, 127);
+    let output = crust.generate_samples(4410);
+    assert!(output.iter().all(|frame| frame[0].is_finite()));
+    let latency = crust.latency() as usize;
+    assert!(output[latency..latency + 10].iter().any(|frame| frame[0].abs() > 0.01));
+}
+
+#[test]
+fn test_program_change() {
+    let mut crust = Crust::default();
+    crust.process_midi_data([192, 2, 0]);
+    assert_eq!(crust.get_preset_num(), 2);
+    assert_eq!(crust.max_voices, 1);
+
+    crust.process_midi_data([192, 100, 0]);
+    assert_eq!(crust.get_preset_num(), FACTORY_PRESETS.len() as i32 - 1);
+}
+
+#[test]
+fn test_note_off_is_continuous() {
+    // Let go during the sustain and in the middle of the decay.
+    for &held_samples in [4410, 1000].iter() {
+        let envelope = Envelope { attack: 0.01, decay: 0.05, sustain: 0.5, release: 0.2, ..Default::default() };
+        let mut crust = Crust { envelope, ..Default::default() };
+        crust.note_on(69, 127);
+        crust.generate_samples(held_samples);
+        let held = crust.notes[0].release_level;
+        assert!(held > 0.0);
+
+        // The first release sample carries on from the last held one, and
+        // each step after that is no bigger than the release ramp's step.
+        crust.note_off(69);
+        let step = held / (crust.envelope.release * 44100.0);
+        let voice = &crust.notes[0];
+        let first = generate_release(&crust.envelope, voice, voice.release_level);
+        assert_eq!(first, held);
+        crust.generate_samples(1);
+        let voice = &crust.notes[0];
+        let second = generate_release(&crust.envelope, voice, voice.release_level);
+        assert!(first - second > 0.0 && first - second <= step + 1e-6);
+    }
+}
+
+#[test]
+fn test_dc_blocker() {
+    let mut blocker = DcBlocker { enabled: 1.0, ..Default::default() };
+    let output: Vec<f32> = (0..88200)
+        .map(|n| {
+            let value = 0.3 + 0.5 * (2.0 * PI * 220.0 * n as f64 / 44100.0).sin() as f32;
+            blocker.process([value, value], 44100.0)[0]
+        })
+        .collect();
+    let mean = |frames: &[f32]| frames.iter().sum::<f32>() / frames.len() as f32;
+    assert!(mean(&output[..441]) > 0.1);
+    assert!(mean(&output[66150..]).abs() < 1e-3);
+    assert!(output[66150..].iter().any(|sample| *sample > 0.45));
+
+    // A narrow pulse wave sits well off centre until the filter is switched on.
+    let pulse_mean = |enabled: f32| {
+        let mut crust = Crust { dc_blocker: DcBlocker { enabled, ..Default::default() }, ..Default::default() };
+        crust.envelope.sustain = 1.0;
+        crust.oscillators[0].wave_index = 2;
+        crust.oscillators[0].pulse_width = 0.1;
+        crust.oscillators[1].volume = SmoothedParam::new(0.0);
+        crust.note_on(57, 127);
+        let output: Vec<f32> = crust.generate_samples(88200).iter().map(|frame| frame[0]).collect();
+        mean(&output[66150..])
+    };
+    assert!(pulse_mean(0.0).abs() > 0.01);
+    assert!(pulse_mean(1.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_phase_reset() {
+    // Two notes played one after the other, with unison copies that would
+    // otherwise start at random phases.
+    let play_twice = |phase_reset: f32| {
+        let mut crust = Crust { phase_reset, unison_count: 3, unison_spread: 0.5, ..Default::default() };
+        crust.oscillators[1].phase_offset = 0.25;
+        crust.note_on(60, 127);
+        let first = crust.generate_samples(2205);
+        crust.panic();
+        crust.note_on(60, 127);
+        (first, crust.generate_samples(2205))
+    };
+    let (first, second) = play_twice(1.0);
+    assert_eq!(first, second);
+    let (first, second) = play_twice(0.0);
+    assert_ne!(first, second);
+
+    // A legato note restarts the playing voice's phases too.
+    let mut crust = Crust { phase_reset: 1.0, voice_mode: 1.0, ..Default::default() };
+    crust.oscillators[1].phase_offset = 0.25;
+    crust.note_on(60, 127);
+    crust.generate_samples(100);
+    crust.note_on(64, 127);
+    assert_eq!(crust.notes.len(), 1);
+    assert_eq!(crust.notes[0].phases[0], [0.0, 0.25]);
+    assert_eq!(crust.notes[0].sub_phase, 0.0);
+}
+
+#[test]
+fn test_noise_seed() {
+    let render = |seed: u64| {
+        let mut crust = Crust { noise: 1.0, noise_only: 1.0, ..Default::default() };
+        crust.noise_generator.color = 0.5;
+        crust.set_noise_seed(seed);
+        crust.note_on(60, 127);
+        crust.generate_samples(4410)
+    };
+    let first = render(42);
+    assert!(first.iter().any(|frame| frame[0].abs() > 0.01));
+    assert_eq!(first, render(42));
+    assert_ne!(first, render(43));
+
+    // Unison start phases and the random arpeggio repeat with the seed too.
+    let render = |seed: u64| {
+        let mut crust = Crust { unison_count: 3, unison_spread: 0.5, ..Default::default() };
+        crust.arpeggiator.enabled = 1.0;
+        crust.arpeggiator.mode = 1.0;
+        crust.arpeggiator.rate = 1.0;
+        crust.set_noise_seed(seed);
+        for &note in [60, 64, 67, 72].iter() {
+            crust.note_on(note, 127);
+        }
+        crust.generate_samples(22050)
+    };
+    let first = render(42);
+    assert_eq!(first, render(42));
+    assert_ne!(first, render(43));
+
+    // Seeding again restarts the same sequence.
+    let mut generator = NoiseGenerator::default();
+    generator.seed(7);
+    let sequence: Vec<f32> = (0..100).map(|_| generator.next(1.0)).collect();
+    generator.seed(7);
+    assert!(sequence.iter().all(|&value| value == generator.next(1.0)));
+}
+
+#[test]
+fn test_sample_and_hold() {
+    // At 20 Hz a new value is picked every 2205 samples and held in between.
+    let mut sample_hold = SampleHold { rate: 1.0, depth: 1.0, ..Default::default() };
+    sample_hold.noise.seed(1);
+    let mut changes = Vec::new();
+    let mut last = sample_hold.value;
+    for n in 0..44100 {
+        sample_hold.advance(1.0 / 44100.0);
+        if sample_hold.value != last {
+            changes.push(n);
+            last = sample_hold.value;
+        }
+        assert!(sample_hold.value.abs() <= 1.0);
+    }
+    assert_eq!(changes.len(), 20);
+    for step in changes.windows(2) {
+        assert!((2204..=2206).contains(&(step[1] - step[0])));
+    }
+
+    assert_eq!(sample_hold.pitch_offset(), sample_hold.value * 12.0);
+    assert_eq!(sample_hold.cutoff_offset(), 0.0);
+    sample_hold.destination = 1.0;
+    assert_eq!(sample_hold.pitch_offset(), 0.0);
+    assert_eq!(sample_hold.cutoff_offset(), Filter::octaves_to_cutoff(sample_hold.value * 4.0));
+
+    // The same seed steps through the same values.
+    let steps = |seed: u64| {
+        let mut crust = Crust::default();
+        crust.sample_hold.rate = 1.0;
+        crust.set_noise_seed(seed);
+        (0..10).map(|_| {
+            crust.generate_samples(2205);
+            crust.sample_hold.value
+        }).collect::<Vec<f32>>()
+    };
+    assert_eq!(steps(5), steps(5));
+    assert_ne!(steps(5), steps(6));
+}
+
+#[test]
+fn test_parameter_defaults_match_new_synth() {
+    let crust = Crust::default();
+    for parameter in PARAMETERS {
+        assert!(((parameter.get)(&crust) - parameter.default).abs() < 1e-6, "{}", parameter.name);
+    }
+}
+
+#[test]
+fn test_migrate_version_1_waveforms() {
+    let mut crust = Crust::default();
+    let mut data = vec![1, 4, 0, 0, 0];
+    for &value in [2.0 / 3.0f32, 0.5, 0.5, 1.0].iter() {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    crust.load_preset_data(&data);
+    assert_eq!(crust.get_parameter_text(0), "Square");
+    assert_eq!(crust.get_parameter_text(3), "Triangle");
+    assert_eq!(crust.get_parameter(1), 0.5);
+    assert_eq!(PARAMETERS[OSC1_WAVEFORM].name, "Osc 1 waveform");
+    assert_eq!(PARAMETERS[OSC2_WAVEFORM].name, "Osc 2 waveform");
+
+    // The editor's handle sees the migrated waveforms too.
+    let shared = crust.shared_parameters();
+    assert_eq!(shared.get(OSC1_WAVEFORM), crust.get_parameter(OSC1_WAVEFORM as i32));
+    assert_eq!(shared.get(OSC2_WAVEFORM), crust.get_parameter(OSC2_WAVEFORM as i32));
+
+    let saved = crust.get_preset_data();
+    crust.load_preset_data(&saved);
+    assert_eq!(crust.get_parameter_text(0), "Square");
+    assert_eq!(crust.get_parameter_text(3), "Triangle");
+}
+
+#[test]
+fn test_load_shared_envelope_curve() {
+    let mut crust = Crust::default();
+    assert_eq!(PARAMETERS[ATTACK_CURVE].name, "Attack curve");
+    assert_eq!(PARAMETERS[DECAY_CURVE].name, "Decay curve");
+    assert_eq!(PARAMETERS[RELEASE_CURVE].name, "Release curve");
+
+    let count = ATTACK_CURVE + 1;
+    let mut data = vec![PRESET_VERSION];
+    data.extend_from_slice(&(count as u32).to_le_bytes());
+    for index in 0..count {
+        let value = if index == ATTACK_CURVE { 0.8f32 } else { crust.get_parameter(index as i32) };
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    crust.load_preset_data(&data);
+    assert_eq!(crust.envelope.attack_curve, 0.8);
+    assert_eq!(crust.envelope.decay_curve, 0.8);
+    assert_eq!(crust.envelope.release_curve, 0.8);
+
+    crust.set_parameter(RELEASE_CURVE as i32, 0.2);
+    let saved = crust.get_preset_data();
+    crust.load_preset_data(&saved);
+    assert_eq!(crust.envelope.release_curve, 0.2);
+}
+
+#[test]
+fn test_normalization_follows_held_voices() {
+    let mut crust = Crust { normalization: 1.0, ..Default::default() };
+    for &note in [60, 64, 67].iter() {
+        crust.note_on(note, 127);
+    }
+    assert_eq!(crust.normalization_gain(), 1.0 / 3.0);
+    crust.generate_samples(1);
+    assert!(crust.normalization_level.current > 0.9);
+    crust.generate_samples(4410);
+    assert!((crust.normalization_level.current - 1.0 / 3.0).abs() < 1e-4);
+
+    // Releasing voices ring out without turning down the next note.
+    crust.note_off(64);
+    crust.note_off(67);
+    assert_eq!(crust.notes.len(), 3);
+    assert_eq!(crust.normalization_gain(), 1.0);
+}
+
+#[test]
+fn test_oversampler_factor_keeps_filters() {
+    let mut oversampler = Oversampler::default();
+    for _ in 0..10 {
+        oversampler.downsample(&[1.0], 0);
+    }
+    let state = oversampler.filters[0][0].z1;
+    let b0 = oversampler.filters[0][0].b0;
+    assert!(state != 0.0);
+
+    // A new factor only retunes the filters, their state carries on.
+    oversampler.set_factor(4);
+    assert_eq!(oversampler.filters[0][0].z1, state);
+    assert!(oversampler.filters[0][0].b0 < b0);
+
+    oversampler.reset();
+    assert!(oversampler.filters.iter().flat_map(|filters| filters.iter()).all(|filter| filter.z1 == 0.0 && filter.z2 == 0.0));
+    assert_eq!(oversampler.factor, 4);
+}
+
+#[test]
+fn test_lfo_waveform_text() {
+    let mut crust = Crust::default();
+    assert_eq!(crust.get_parameter_text(19), "Sine");
+    crust.set_parameter(19, 0.5);
+    assert_eq!(crust.get_parameter_text(19), "Triangle");
+    crust.set_parameter(19, 1.0);
+    assert_eq!(crust.get_parameter_text(19), "Square");
+}
+
+#[test]
+fn test_process_reuses_block_buffer() {
+    let mut crust = Crust::default();
+    crust.set_block_size(4096);
+    assert!(crust.block.capacity() >= 4096);
+    crust.note_on(69, 127);
+
+    let inputs: Vec<Vec<f32>> = Vec::new();
+    let mut outputs = vec![vec![0.0_f32; 4096]; 2];
+    crust.process(&mut HostBuffer::new(0, 2).bind(&inputs, &mut outputs));
+    let buffer = crust.block.as_ptr();
+    crust.process(&mut HostBuffer::new(0, 2).bind(&inputs, &mut outputs));
+    assert_eq!(crust.block.as_ptr(), buffer);
+    assert!(outputs[0].iter().any(|sample| sample.abs() > 0.01));
 }